@@ -0,0 +1,575 @@
+use std::fmt;
+use std::num::IntErrorKind;
+use ::*;
+
+/* How deep expressions can be nested, counting both parentheses and operators
+ * in the text and variables holding expressions of their own */
+const MAX_DEPTH: usize = 100;
+
+#[derive(PartialEq, Debug)]
+pub enum ArithError {
+    /* The text of the token the error was found at */
+    Syntax(String),
+    DivideByZero,
+    Overflow,
+    NegativeExponent,
+    InvalidNumber(String),
+    TooDeep,
+}
+
+impl fmt::Display for ArithError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArithError::Syntax(ref tok) if tok.is_empty() => write!(f, "syntax error: operand expected"),
+            ArithError::Syntax(ref tok) => write!(f, "syntax error in expression (error token is \"{}\")", tok),
+            ArithError::DivideByZero => write!(f, "division by 0"),
+            ArithError::Overflow => write!(f, "integer overflow"),
+            ArithError::NegativeExponent => write!(f, "exponent less than 0"),
+            ArithError::InvalidNumber(ref num) => write!(f, "{}: invalid number", num),
+            ArithError::TooDeep => write!(f, "expression recursion level exceeded"),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Token {
+    Num(String),
+    Name(String),
+    Op(&'static str),
+}
+
+/* Longer operators come first, so that they are matched before their
+ * prefixes */
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=", ",", "(", ")",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, ArithError> {
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+
+    loop {
+        rest = rest.trim_start();
+
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => return Ok(tokens),
+        };
+
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+
+            if c.is_ascii_digit() {
+                tokens.push(Token::Num(rest[..len].to_string()));
+            } else {
+                tokens.push(Token::Name(rest[..len].to_string()));
+            }
+
+            rest = &rest[len..];
+            continue;
+        }
+
+        match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            Some(op) => {
+                tokens.push(Token::Op(op));
+                rest = &rest[op.len()..];
+            },
+            None => return Err(ArithError::Syntax(rest.to_string())),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum BinOp {
+    Mul, Div, Rem, Pow,
+    Add, Sub,
+    Shl, Shr,
+    Lt, Gt, Le, Ge, Eq, Ne,
+    BitAnd, BitXor, BitOr,
+    And, Or,
+}
+
+/* The binary operators, with how tightly they bind */
+const BINARY_OPS: &[(&str, BinOp, u32)] = &[
+    ("||", BinOp::Or, 1),
+    ("&&", BinOp::And, 2),
+    ("|", BinOp::BitOr, 3),
+    ("^", BinOp::BitXor, 4),
+    ("&", BinOp::BitAnd, 5),
+    ("==", BinOp::Eq, 6),
+    ("!=", BinOp::Ne, 6),
+    ("<", BinOp::Lt, 7),
+    (">", BinOp::Gt, 7),
+    ("<=", BinOp::Le, 7),
+    (">=", BinOp::Ge, 7),
+    ("<<", BinOp::Shl, 8),
+    (">>", BinOp::Shr, 8),
+    ("+", BinOp::Add, 9),
+    ("-", BinOp::Sub, 9),
+    ("*", BinOp::Mul, 10),
+    ("/", BinOp::Div, 10),
+    ("%", BinOp::Rem, 10),
+    ("**", BinOp::Pow, 11),
+];
+
+/* The assignment operators, along with the operator they apply first */
+const ASSIGN_OPS: &[(&str, Option<BinOp>)] = &[
+    ("=", None),
+    ("*=", Some(BinOp::Mul)),
+    ("/=", Some(BinOp::Div)),
+    ("%=", Some(BinOp::Rem)),
+    ("+=", Some(BinOp::Add)),
+    ("-=", Some(BinOp::Sub)),
+    ("<<=", Some(BinOp::Shl)),
+    (">>=", Some(BinOp::Shr)),
+    ("&=", Some(BinOp::BitAnd)),
+    ("^=", Some(BinOp::BitXor)),
+    ("|=", Some(BinOp::BitOr)),
+];
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum UnaryOp {
+    Plus,
+    Minus,
+    Not,
+    BitNot,
+}
+
+#[derive(PartialEq, Debug)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(String, Option<BinOp>, Box<Expr>),
+    /* '++name' and '--name' add their amount before giving the value, and
+     * 'name++' and 'name--' after */
+    PreIncrement(String, i64),
+    PostIncrement(String, i64),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+/* Parses integer constants, which are in octal when they start with '0' and
+ * in hex when they start with '0x' */
+fn parse_number(s: &str) -> Result<i64, ArithError> {
+    let result = if s.starts_with("0x") || s.starts_with("0X") {
+        i64::from_str_radix(&s[2..], 16)
+    } else if s.len() > 1 && s.starts_with('0') {
+        i64::from_str_radix(&s[1..], 8)
+    } else {
+        s.parse::<i64>()
+    };
+
+    result.map_err(|err| match *err.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => ArithError::Overflow,
+        _ => ArithError::InvalidNumber(s.to_string()),
+    })
+}
+
+struct ArithParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /* How deep the expression being parsed is nested */
+    depth: usize,
+}
+
+impl ArithParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn error(&self) -> ArithError {
+        let rest: Vec<String> = self.tokens[self.pos..].iter().map(|tok| match *tok {
+            Token::Num(ref s) | Token::Name(ref s) => s.clone(),
+            Token::Op(op) => op.to_string(),
+        }).collect();
+
+        ArithError::Syntax(rest.join(" "))
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), ArithError> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /* Parses an expression nested inside another one, unless that would go
+     * past MAX_DEPTH and run the parser out of stack */
+    fn parse_nested<F>(&mut self, parse: F) -> Result<Expr, ArithError>
+        where F: FnOnce(&mut ArithParser) -> Result<Expr, ArithError>
+    {
+        if self.depth >= MAX_DEPTH {
+            return Err(ArithError::TooDeep);
+        }
+
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+
+        expr
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, ArithError> {
+        let mut expr = self.parse_assign()?;
+
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Comma(Box::new(expr), Box::new(self.parse_assign()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_assign(&mut self) -> Result<Expr, ArithError> {
+        if let Some(Token::Name(name)) = self.peek() {
+            let op = match self.tokens.get(self.pos + 1) {
+                Some(Token::Op(op)) => ASSIGN_OPS.iter().find(|(s, _)| s == op),
+                _ => None,
+            };
+
+            if let Some((_, op)) = op {
+                let name = name.clone();
+                self.pos += 2;
+                return Ok(Expr::Assign(name, *op, Box::new(self.parse_nested(ArithParser::parse_assign)?)));
+            }
+        }
+
+        self.parse_conditional()
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr, ArithError> {
+        let cond = self.parse_binary(1)?;
+
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+
+        self.pos += 1;
+        let then_expr = self.parse_nested(ArithParser::parse_comma)?;
+        self.expect_op(":")?;
+        let else_expr = self.parse_nested(ArithParser::parse_conditional)?;
+
+        Ok(Expr::Conditional(Box::new(cond), Box::new(then_expr), Box::new(else_expr)))
+    }
+
+    /* Parses the binary operators that bind at least as tightly as
+     * 'min_prec'. '**' groups from the right, and the rest from the left. */
+    fn parse_binary(&mut self, min_prec: u32) -> Result<Expr, ArithError> {
+        let mut lhs = self.parse_unary()?;
+
+        loop {
+            let found = match self.peek_op() {
+                Some(op) => BINARY_OPS.iter().find(|(s, _, _)| *s == op),
+                None => None,
+            };
+
+            let (op, prec) = match found {
+                Some(&(_, op, prec)) if prec >= min_prec => (op, prec),
+                _ => return Ok(lhs),
+            };
+
+            self.pos += 1;
+
+            let rhs_prec = if op == BinOp::Pow { prec } else { prec + 1 };
+            let rhs = self.parse_nested(|parser| parser.parse_binary(rhs_prec))?;
+
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ArithError> {
+        let op = match self.peek_op() {
+            Some("+") => UnaryOp::Plus,
+            Some("-") => UnaryOp::Minus,
+            Some("!") => UnaryOp::Not,
+            Some("~") => UnaryOp::BitNot,
+            Some(op) if op == "++" || op == "--" => {
+                if let Some(Token::Name(name)) = self.tokens.get(self.pos + 1) {
+                    let name = name.clone();
+                    self.pos += 2;
+                    return Ok(Expr::PreIncrement(name, if op == "++" { 1 } else { -1 }));
+                }
+
+                return Err(self.error());
+            },
+            _ => return self.parse_postfix(),
+        };
+
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.parse_nested(ArithParser::parse_unary)?)))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, ArithError> {
+        let tok = match self.peek() {
+            Some(tok) => tok.clone(),
+            None => return Err(ArithError::Syntax(String::new())),
+        };
+
+        match tok {
+            Token::Num(s) => {
+                self.pos += 1;
+                Ok(Expr::Num(parse_number(&s)?))
+            },
+            Token::Name(name) => {
+                self.pos += 1;
+
+                match self.peek_op() {
+                    Some("++") => {
+                        self.pos += 1;
+                        Ok(Expr::PostIncrement(name, 1))
+                    },
+                    Some("--") => {
+                        self.pos += 1;
+                        Ok(Expr::PostIncrement(name, -1))
+                    },
+                    _ => Ok(Expr::Var(name)),
+                }
+            },
+            Token::Op("(") => {
+                self.pos += 1;
+                let expr = self.parse_nested(ArithParser::parse_comma)?;
+                self.expect_op(")")?;
+                Ok(expr)
+            },
+            Token::Op(_) => Err(self.error()),
+        }
+    }
+}
+
+fn apply(op: BinOp, a: i64, b: i64) -> Result<i64, ArithError> {
+    let result = match op {
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        BinOp::Mul => a.checked_mul(b),
+        BinOp::Div | BinOp::Rem if b == 0 => return Err(ArithError::DivideByZero),
+        BinOp::Div => a.checked_div(b),
+        /* Which only overflows for the smallest number, whose remainder is
+         * still 0 */
+        BinOp::Rem if b == -1 => Some(0),
+        BinOp::Rem => a.checked_rem(b),
+        BinOp::Pow => {
+            if b < 0 {
+                return Err(ArithError::NegativeExponent);
+            }
+
+            if b > u32::MAX as i64 {
+                if a == 0 || a == 1 { Some(a) } else { None }
+            } else {
+                a.checked_pow(b as u32)
+            }
+        },
+        BinOp::Shl | BinOp::Shr if !(0..64).contains(&b) => None,
+        BinOp::Shl => Some(a << b),
+        BinOp::Shr => Some(a >> b),
+        BinOp::Lt => Some((a < b) as i64),
+        BinOp::Gt => Some((a > b) as i64),
+        BinOp::Le => Some((a <= b) as i64),
+        BinOp::Ge => Some((a >= b) as i64),
+        BinOp::Eq => Some((a == b) as i64),
+        BinOp::Ne => Some((a != b) as i64),
+        BinOp::BitAnd => Some(a & b),
+        BinOp::BitXor => Some(a ^ b),
+        BinOp::BitOr => Some(a | b),
+        BinOp::And => Some((a != 0 && b != 0) as i64),
+        BinOp::Or => Some((a != 0 || b != 0) as i64),
+    };
+
+    result.ok_or(ArithError::Overflow)
+}
+
+/* The value of a variable, which can itself hold an expression. Unset and
+ * empty variables are 0. */
+fn var_value(name: &str, depth: usize) -> Result<i64, ArithError> {
+    let value = VARIABLES.lock().unwrap().get(name).unwrap_or("").to_string();
+
+    eval_depth(&value, depth + 1)
+}
+
+fn set_var(name: &str, value: i64) {
+    VARIABLES.lock().unwrap().set(name, &value.to_string());
+}
+
+fn eval_expr(expr: &Expr, depth: usize) -> Result<i64, ArithError> {
+    match *expr {
+        Expr::Num(n) => Ok(n),
+        Expr::Var(ref name) => var_value(name, depth),
+        Expr::Unary(op, ref e) => {
+            let n = eval_expr(e, depth)?;
+
+            match op {
+                UnaryOp::Plus => Ok(n),
+                UnaryOp::Minus => n.checked_neg().ok_or(ArithError::Overflow),
+                UnaryOp::Not => Ok((n == 0) as i64),
+                UnaryOp::BitNot => Ok(!n),
+            }
+        },
+        /* The right side of '&&' and '||' is only evaluated when it's needed */
+        Expr::Binary(BinOp::And, ref a, ref b) => {
+            Ok((eval_expr(a, depth)? != 0 && eval_expr(b, depth)? != 0) as i64)
+        },
+        Expr::Binary(BinOp::Or, ref a, ref b) => {
+            Ok((eval_expr(a, depth)? != 0 || eval_expr(b, depth)? != 0) as i64)
+        },
+        Expr::Binary(op, ref a, ref b) => {
+            let a = eval_expr(a, depth)?;
+            let b = eval_expr(b, depth)?;
+            apply(op, a, b)
+        },
+        Expr::Conditional(ref cond, ref a, ref b) => {
+            if eval_expr(cond, depth)? != 0 {
+                eval_expr(a, depth)
+            } else {
+                eval_expr(b, depth)
+            }
+        },
+        Expr::Assign(ref name, op, ref e) => {
+            let rhs = eval_expr(e, depth)?;
+
+            let value = match op {
+                Some(op) => apply(op, var_value(name, depth)?, rhs)?,
+                None => rhs,
+            };
+
+            set_var(name, value);
+            Ok(value)
+        },
+        Expr::PreIncrement(ref name, amount) => {
+            let value = apply(BinOp::Add, var_value(name, depth)?, amount)?;
+            set_var(name, value);
+            Ok(value)
+        },
+        Expr::PostIncrement(ref name, amount) => {
+            let old = var_value(name, depth)?;
+            set_var(name, apply(BinOp::Add, old, amount)?);
+            Ok(old)
+        },
+        Expr::Comma(ref a, ref b) => {
+            eval_expr(a, depth)?;
+            eval_expr(b, depth)
+        },
+    }
+}
+
+fn eval_depth(expr: &str, depth: usize) -> Result<i64, ArithError> {
+    if depth > MAX_DEPTH {
+        return Err(ArithError::TooDeep);
+    }
+
+    let tokens = tokenize(expr)?;
+
+    /* An empty expression is 0 */
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut parser = ArithParser { tokens, pos: 0, depth };
+    let parsed = parser.parse_comma()?;
+
+    if parser.pos != parser.tokens.len() {
+        return Err(parser.error());
+    }
+
+    eval_expr(&parsed, depth)
+}
+
+/* Evaluates the text of an arithmetic expansion, after its parameters have
+ * been expanded */
+pub fn eval(expr: &str) -> Result<i64, ArithError> {
+    eval_depth(expr, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("1 | 6 ^ 3 & 5"), Ok(7));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(eval("0x1F"), Ok(31));
+        assert_eq!(eval("017"), Ok(15));
+        assert_eq!(eval("08"), Err(ArithError::InvalidNumber("08".to_string())));
+        assert_eq!(eval("12abc"), Err(ArithError::InvalidNumber("12abc".to_string())));
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(eval("3 > 2 && 2 >= 2 && 1 < 2 && 2 <= 1"), Ok(0));
+        assert_eq!(eval("1 == 1 || 1 / 0"), Ok(1));
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+        assert_eq!(eval("1 ? 2 : 3"), Ok(2));
+        assert_eq!(eval("0 ? 2 : 0 ? 3 : 4"), Ok(4));
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("arith_a = 5, arith_a *= 3, arith_a"), Ok(15));
+        assert_eq!(eval("arith_a++"), Ok(15));
+        assert_eq!(eval("--arith_a"), Ok(15));
+        assert_eq!(eval("arith_a <<= 1"), Ok(30));
+        assert_eq!(VARIABLES.lock().unwrap().get("arith_a"), Some("30"));
+        assert_eq!(eval("arith_unset + 1"), Ok(1));
+
+        VARIABLES.lock().unwrap().set("arith_ref", "arith_a + 1");
+        assert_eq!(eval("arith_ref * 2"), Ok(62));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("1 / 0"), Err(ArithError::DivideByZero));
+        assert_eq!(eval("1 % 0"), Err(ArithError::DivideByZero));
+        assert_eq!(eval("9223372036854775807 + 1"), Err(ArithError::Overflow));
+        assert_eq!(eval("-9223372036854775807 - 2"), Err(ArithError::Overflow));
+        assert_eq!(eval("99999999999999999999"), Err(ArithError::Overflow));
+        assert_eq!(eval("1 << 64"), Err(ArithError::Overflow));
+        assert_eq!(eval("2 ** -1"), Err(ArithError::NegativeExponent));
+        assert_eq!(eval("1 +"), Err(ArithError::Syntax(String::new())));
+        assert_eq!(eval("(1"), Err(ArithError::Syntax(String::new())));
+        assert_eq!(eval("1 2"), Err(ArithError::Syntax("2".to_string())));
+        assert_eq!(eval("1 @ 2"), Err(ArithError::Syntax("@ 2".to_string())));
+        assert_eq!(eval("3 = 4"), Err(ArithError::Syntax("= 4".to_string())));
+
+        VARIABLES.lock().unwrap().set("arith_loop", "arith_loop");
+        assert_eq!(eval("arith_loop"), Err(ArithError::TooDeep));
+
+        assert_eq!(eval(&format!("{}1{}", "(".repeat(100000), ")".repeat(100000))), Err(ArithError::TooDeep));
+        assert_eq!(eval(&format!("{}1", "- ".repeat(100000))), Err(ArithError::TooDeep));
+        assert_eq!(eval(&format!("{}1", "! ~ ".repeat(50000))), Err(ArithError::TooDeep));
+        assert_eq!(eval(&format!("{}1", "2 ** ".repeat(100000))), Err(ArithError::TooDeep));
+        assert_eq!(eval(&format!("{}1", "1 ? 1 : ".repeat(100000))), Err(ArithError::TooDeep));
+        assert_eq!(eval(&format!("{}1", "arith_b = ".repeat(100000))), Err(ArithError::TooDeep));
+    }
+
+    #[test]
+    fn remainders() {
+        assert_eq!(eval("7 % -3"), Ok(1));
+        assert_eq!(eval("-7 % 3"), Ok(-1));
+        assert_eq!(eval("(-9223372036854775807 - 1) % -1"), Ok(0));
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1"), Err(ArithError::Overflow));
+    }
+}
@@ -0,0 +1,344 @@
+use std::fmt;
+use var::is_valid_name;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum WordPart {
+    Literal(String),
+    /* Quoted text is never expanded, split into fields, or globbed */
+    SingleQuoted(String),
+    Escaped(char),
+    /* Parameters in a double quoted part are expanded, but the results are
+     * not split into fields */
+    DoubleQuoted(Vec<WordPart>),
+    Param(String),
+    /* The source of a '$(...)' or '`...`' command substitution, which is
+     * parsed when it is run */
+    CommandSubst(String),
+    /* The expression of a '$((...))' arithmetic expansion, which is expanded
+     * like it was in double quotes before being evaluated */
+    Arith(Vec<WordPart>),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    /* The text of a word made up of nothing but unquoted literal text, such as
+     * a reserved word */
+    pub fn as_literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(s)] => Some(s),
+            _ => None,
+        }
+    }
+
+    /* Whether expanding the word runs a command substitution */
+    pub fn has_command_subst(&self) -> bool {
+        fn any_subst(parts: &[WordPart]) -> bool {
+            parts.iter().any(|part| match *part {
+                WordPart::CommandSubst(_) => true,
+                WordPart::DoubleQuoted(ref inner) | WordPart::Arith(ref inner) => any_subst(inner),
+                _ => false,
+            })
+        }
+
+        any_subst(&self.parts)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum RedirectKind {
+    In,
+    Out,
+    AppendOut,
+    /* '>|', which overwrites the file even with 'noclobber' set */
+    Clobber,
+    ReadWrite,
+    /* '<&' and '>&', where the target is another fd to copy, or '-' to close
+     * the fd */
+    DupIn,
+    DupOut,
+    /* '&>' and '&>>', for both stdout and stderr */
+    AllOut,
+    AllAppendOut,
+    /* A here-document with its delimiter. The target is the body. */
+    HereDoc(String),
+    /* '<<<', where the target is given as the input, with a newline after */
+    HereString,
+}
+
+impl RedirectKind {
+    /* The fd a redirection applies to when no number is given */
+    pub fn default_fd(&self) -> i32 {
+        match *self {
+            RedirectKind::In | RedirectKind::ReadWrite | RedirectKind::DupIn
+                | RedirectKind::HereDoc(_) | RedirectKind::HereString => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Redirect {
+    /* The fd number written before the operator, if there was one */
+    pub fd: Option<i32>,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum CompoundCommand {
+    Subshell(CommandList),
+    BraceGroup(CommandList),
+    /* Every 'if' and 'elif' condition paired with its 'then' body */
+    If(Vec<(CommandList, CommandList)>, Option<CommandList>),
+    While(CommandList, CommandList),
+    Until(CommandList, CommandList),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Pipeline {
+    pub negate: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AndOrOp {
+    And,
+    Or,
+}
+
+/* A chain of pipelines joined by '&&' and '||'. The first pipeline always
+ * runs, every following pipeline only runs if the exit status of the last
+ * pipeline that ran says it should. */
+#[derive(PartialEq, Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub is_background: bool,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CommandList {
+    pub items: Vec<ListItem>,
+}
+
+impl CommandList {
+    pub fn new() -> CommandList {
+        CommandList { items: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+fn fmt_parts(f: &mut fmt::Formatter, parts: &[WordPart]) -> fmt::Result {
+    for (i, part) in parts.iter().enumerate() {
+        match *part {
+            WordPart::Literal(ref s) => write!(f, "{}", s)?,
+            WordPart::SingleQuoted(ref s) => write!(f, "'{}'", s)?,
+            WordPart::Escaped(c) => write!(f, "\\{}", c)?,
+            WordPart::DoubleQuoted(ref inner) => {
+                write!(f, "\"")?;
+                fmt_parts(f, inner)?;
+                write!(f, "\"")?;
+            },
+            WordPart::Param(ref name) => {
+                /* Braces are only needed if the name would run into the text
+                 * after it, or if it can't be written without them */
+                let runs_on = match parts.get(i + 1) {
+                    Some(WordPart::Literal(s)) => s.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+                    _ => false,
+                };
+                let needs_braces = runs_on || (name.chars().count() > 1 && !is_valid_name(name));
+
+                if needs_braces {
+                    write!(f, "${{{}}}", name)?;
+                } else {
+                    write!(f, "${}", name)?;
+                }
+            },
+            WordPart::CommandSubst(ref source) => write!(f, "$({})", source)?,
+            WordPart::Arith(ref expr) => {
+                write!(f, "$((")?;
+                fmt_parts(f, expr)?;
+                write!(f, "))")?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_parts(f, &self.parts)
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.kind {
+            RedirectKind::In => "<",
+            RedirectKind::Out => ">",
+            RedirectKind::AppendOut => ">>",
+            RedirectKind::Clobber => ">|",
+            RedirectKind::ReadWrite => "<>",
+            RedirectKind::DupIn => "<&",
+            RedirectKind::DupOut => ">&",
+            RedirectKind::AllOut => "&>",
+            RedirectKind::AllAppendOut => "&>>",
+            RedirectKind::HereString => "<<<",
+            RedirectKind::HereDoc(_) => "<<",
+        };
+
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+
+        if let RedirectKind::HereDoc(ref delim) = self.kind {
+            return write!(f, "{}{}", op, delim);
+        }
+
+        write!(f, "{} {}", op, self.target)
+    }
+}
+
+fn fmt_redirects(f: &mut fmt::Formatter, redirects: &[Redirect]) -> fmt::Result {
+    for redirect in redirects {
+        write!(f, " {}", redirect)?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for SimpleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for assignment in &self.assignments {
+            write!(f, "{}{}={}", if first { "" } else { " " }, assignment.name, assignment.value)?;
+            first = false;
+        }
+
+        for word in &self.words {
+            write!(f, "{}{}", if first { "" } else { " " }, word)?;
+            first = false;
+        }
+
+        fmt_redirects(f, &self.redirects)
+    }
+}
+
+impl fmt::Display for CompoundCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompoundCommand::Subshell(ref list) => write!(f, "( {} )", list),
+            CompoundCommand::BraceGroup(ref list) => write!(f, "{{ {}; }}", list),
+            CompoundCommand::If(ref branches, ref else_part) => {
+                for (i, (cond, body)) in branches.iter().enumerate() {
+                    write!(f, "{} {}; then {}; ", if i == 0 { "if" } else { "elif" }, cond, body)?;
+                }
+
+                if let Some(ref else_part) = *else_part {
+                    write!(f, "else {}; ", else_part)?;
+                }
+
+                write!(f, "fi")
+            },
+            CompoundCommand::While(ref cond, ref body) => write!(f, "while {}; do {}; done", cond, body),
+            CompoundCommand::Until(ref cond, ref body) => write!(f, "until {}; do {}; done", cond, body),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Simple(ref simple) => write!(f, "{}", simple),
+            Command::Compound(ref compound, ref redirects) => {
+                write!(f, "{}", compound)?;
+                fmt_redirects(f, redirects)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negate {
+            write!(f, "! ")?;
+        }
+
+        for (i, cmd) in self.commands.iter().enumerate() {
+            if i != 0 {
+                write!(f, " | ")?;
+            }
+
+            write!(f, "{}", cmd)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+
+        for &(op, ref pipeline) in &self.rest {
+            write!(f, " {} {}", if op == AndOrOp::And { "&&" } else { "||" }, pipeline)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for CommandList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i != 0 {
+                if self.items[i - 1].is_background {
+                    write!(f, " ")?;
+                } else {
+                    write!(f, "; ")?;
+                }
+            }
+
+            write!(f, "{}", item.and_or)?;
+
+            if item.is_background {
+                write!(f, " &")?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,10 +1,17 @@
 
 use prog::*;
+use job::*;
+use job_list::*;
+use exec;
+use signal;
+use std::sync::*;
+use var::*;
+use options::*;
 use std::fmt;
 use std::collections::*;
 use ::*;
 
-pub type BuiltinCallback = fn(&mut Prog) -> usize;
+pub type BuiltinCallback = fn(&mut Prog) -> i32;
 
 pub struct ShellBuiltin {
     pub callback: BuiltinCallback,
@@ -32,15 +39,21 @@ lazy_static! {
         m.insert("fg",   ShellBuiltin::new(builtin_fg));
         m.insert("bg",   ShellBuiltin::new(builtin_bg));
         m.insert("echo", ShellBuiltin::new(builtin_echo));
+        m.insert("export", ShellBuiltin::new(builtin_export));
+        m.insert("unset", ShellBuiltin::new(builtin_unset));
+        m.insert("env",  ShellBuiltin::new(builtin_env));
+        m.insert("set",  ShellBuiltin::new(builtin_set));
+        m.insert("umask", ShellBuiltin::new(builtin_umask));
+        m.insert("exit", ShellBuiltin::new(builtin_exit));
+        m.insert("disown", ShellBuiltin::new(builtin_disown));
+        m.insert("kill", ShellBuiltin::new(builtin_kill));
+        m.insert("wait", ShellBuiltin::new(builtin_wait));
         m
     };
 }
 
 pub fn builtin_find_callback(s: &str) -> Option<ShellBuiltin> {
-    match BUILTIN_MAP.get(s) {
-        Some(builtin) => Some(*builtin),
-        None => None
-    }
+    BUILTIN_MAP.get(s).copied()
 }
 
 impl fmt::Debug for ShellBuiltin {
@@ -53,90 +66,493 @@ fn fd_print(fd: libc::c_int, s: &str) -> usize {
     unsafe { libc::write(fd, s.as_ptr() as *const libc::c_void, s.len()) as usize }
 }
 
-fn builtin_jobs(prog: &mut Prog) -> usize {
+/* The job a builtin like 'fg' is given, as a job spec or a plain job number.
+ * Without one it is the current job. */
+fn job_arg(job_list: &JobList, arg: Option<&String>) -> Result<Arc<Mutex<Job>>, JobSpecError> {
+    match arg {
+        Some(arg) => job_list.find_job(arg),
+        None => job_list.find_job("%+").map_err(|_| JobSpecError::NoSuchJob("current".to_string())),
+    }
+}
+
+/* The options 'jobs' was given */
+#[derive(PartialEq, Debug, Default)]
+struct JobsOptions {
+    long: bool,
+    pgrps_only: bool,
+    state_only: Option<JobState>,
+}
+
+/* Splits the arguments of 'jobs' into its options and the job operands after
+ * them, or gives the option letter that isn't valid */
+fn jobs_options(mut args: &[String]) -> Result<(JobsOptions, &[String]), char> {
+    let mut options = JobsOptions::default();
+
+    while let Some(arg) = args.first() {
+        if !arg.starts_with('-') || arg == "-" {
+            break;
+        }
+
+        args = &args[1..];
+
+        if arg == "--" {
+            break;
+        }
+
+        for c in arg[1..].chars() {
+            match c {
+                'l' => options.long = true,
+                'p' => options.pgrps_only = true,
+                'r' => options.state_only = Some(JobState::Running),
+                's' => options.state_only = Some(JobState::Stopped),
+                _ => return Err(c),
+            }
+        }
+    }
+
+    Ok((options, args))
+}
+
+/* 'jobs [-lprs] [job...]' lists the jobs. '-l' shows the pid and state of
+ * every prog in a job, '-p' only the process group of each job, and '-r' and
+ * '-s' only list running or stopped jobs. */
+fn builtin_jobs(prog: &mut Prog) -> i32 {
     let job_list = JOB_LIST.lock().unwrap();
-    let mut i: usize = 0;
-    let mut ret: usize = 0;
+    let mut ret = 0;
+
+    let (options, args) = match jobs_options(&prog.args) {
+        Ok(parsed) => parsed,
+        Err(c) => {
+            fd_print(prog.stderr(), &format!("jobs: -{}: invalid option\n", c));
+            return 2;
+        },
+    };
+
+    let jobs: Vec<Result<Arc<Mutex<Job>>, JobSpecError>> = if args.is_empty() {
+        job_list.list.iter().cloned().map(Ok).collect()
+    } else {
+        args.iter().map(|arg| job_list.find_job(arg)).collect()
+    };
+
+    for job in &jobs {
+        let real_job = match *job {
+            Ok(ref job) => job.lock().unwrap(),
+            Err(ref err) => {
+                fd_print(prog.stderr(), &format!("jobs: {}\n", err));
+                ret = 1;
+                continue;
+            },
+        };
+
+        if options.state_only.as_ref().is_some_and(|state| *state != real_job.state) {
+            continue;
+        }
+
+        if options.pgrps_only {
+            fd_print(prog.stdout(), &format!("{}\n", real_job.pgrp));
+            continue;
+        }
 
-    for job in &job_list.list {
-        i += 1;
+        let state = if real_job.state == JobState::Running { "Running" } else { "Stopped" };
+        let prefix = format!("[{}]{} ", real_job.id, job_list.marker(real_job.id));
 
-        let real_job = job.lock().unwrap();
+        if !options.long {
+            fd_print(prog.stdout(), &format!("{}{}: {}\n", prefix, state, real_job.name));
+            continue;
+        }
 
-        ret = fd_print(prog.stdout, &format!("[{}] {}: {}\n",
-                                             i,
-                                             if real_job.state == JobState::Running { "Running" } else { "Stopped" },
-                                             real_job.name));
+        /* Progs that have finished show how they finished, and the rest
+         * show the state of the job */
+        for (i, p) in real_job.progs.iter().enumerate() {
+            let status = match p.status {
+                Some(status) => status.to_string(),
+                None => state.to_string(),
+            };
+            let name = if p.name.is_empty() { &real_job.name } else { &p.name };
+
+            fd_print(prog.stdout(), &format!("{:width$}{} {}: {}\n",
+                                             if i == 0 { prefix.as_str() } else { "" },
+                                             p.pid,
+                                             status,
+                                             name,
+                                             width = prefix.len()));
+        }
     }
 
-    return ret;
+    ret
 }
 
-fn builtin_pwd(prog: &mut Prog) -> usize {
+fn builtin_pwd(prog: &mut Prog) -> i32 {
     let cwd = CURRENT_DIR.lock().unwrap();
 
-    fd_print(prog.stdout, &format!("{}\n", *cwd))
+    fd_print(prog.stdout(), &format!("{}\n", *cwd));
+
+    0
 }
 
-fn builtin_fg(prog: &mut Prog) -> usize {
-    let job_id;
+fn builtin_fg(prog: &mut Prog) -> i32 {
+    let mut job_list = JOB_LIST.lock().unwrap();
 
-    if prog.args.len() < 1 {
-        job_id = 1;
-    } else {
-        job_id = prog.args[0].parse::<usize>().unwrap();
+    if !job_list.job_control {
+        fd_print(prog.stderr(), "fg: no job control\n");
+        return 1;
     }
 
+    let j = match job_arg(&job_list, prog.args.first()) {
+        Ok(j) => j,
+        Err(err) => {
+            fd_print(prog.stderr(), &format!("fg: {}\n", err));
+            return 1;
+        },
+    };
+
+    let (id, name) = {
+        let job = j.lock().unwrap();
+        (job.id, job.name.clone())
+    };
+
+    fd_print(prog.stdout(), &format!("{}\n", name));
+
+    job_list.set_current(id);
+    job_list.set_forground_job(Some(j));
+
+    0
+}
+
+fn builtin_bg(prog: &mut Prog) -> i32 {
     let mut job_list = JOB_LIST.lock().unwrap();
-    let real_job_id = job_id - 1;
 
-    if real_job_id >= job_list.list.len() {
-        fd_print(prog.stdout, &format!("bg: Unknown job {}\n", job_id));
+    if !job_list.job_control {
+        fd_print(prog.stderr(), "bg: no job control\n");
         return 1;
     }
 
-    let j = job_list.list[real_job_id].clone();
+    let lck = match job_arg(&job_list, prog.args.first()) {
+        Ok(j) => j,
+        Err(err) => {
+            fd_print(prog.stderr(), &format!("bg: {}\n", err));
+            return 1;
+        },
+    };
+    let mut j = lck.lock().unwrap();
 
-    job_list.set_forground_job(Some(j));
+    j.cont();
+    job_list.set_current(j.id);
+
+    fd_print(prog.stdout(), &format!("[{}]{} {} &\n", j.id, job_list.marker(j.id), j.name));
 
     0
 }
 
-fn builtin_bg(prog: &mut Prog) -> usize {
-    let job_id;
+/* 'disown [job]' removes a job from the job list, so that it isn't sent
+ * SIGHUP when the shell exits. Without a job it takes the current job. */
+fn builtin_disown(prog: &mut Prog) -> i32 {
+    let mut job_list = JOB_LIST.lock().unwrap();
 
-    if prog.args.len() < 1 {
-        job_id = 1;
-    } else {
-        job_id = prog.args[0].parse::<usize>().unwrap();
+    match job_arg(&job_list, prog.args.first()) {
+        Ok(job) => {
+            job_list.remove(&job);
+            0
+        },
+        Err(err) => {
+            fd_print(prog.stderr(), &format!("disown: {}\n", err));
+            1
+        },
     }
+}
+
+/* What 'kill -l' prints for a signal: the name for a number or an exit
+ * status of a command killed by a signal, and the number for a name */
+fn signal_text(arg: &str) -> Option<String> {
+    match arg.parse::<libc::c_int>() {
+        Ok(n) => {
+            let sig = if n > 128 { n - 128 } else { n };
+            let name = signal::signal_name(sig);
+
+            name.strip_prefix("SIG").map(|name| name.to_string())
+        },
+        Err(_) => signal::signal_number(arg).map(|sig| sig.to_string()),
+    }
+}
+
+/* 'kill -l [sig...]' lists the signals, or turns each signal number or exit
+ * status into a name and each name into a number */
+fn list_signals(prog: &mut Prog, args: &[String]) -> i32 {
+    let mut ret = 0;
+
+    if args.is_empty() {
+        let sigs = signal::all_signals();
+
+        for (i, sig) in sigs.iter().enumerate() {
+            let end = if i % 5 == 4 || i + 1 == sigs.len() { "\n" } else { "\t" };
+
+            fd_print(prog.stdout(), &format!("{:2}) {}{}", sig, signal::signal_name(*sig), end));
+        }
+
+        return 0;
+    }
+
+    for arg in args {
+        match signal_text(arg) {
+            Some(text) => { fd_print(prog.stdout(), &format!("{}\n", text)); },
+            None => {
+                fd_print(prog.stderr(), &format!("kill: {}: invalid signal specification\n", arg));
+                ret = 1;
+            },
+        }
+    }
+
+    ret
+}
+
+/* How 'kill' was called, once its options are parsed */
+#[derive(PartialEq, Debug)]
+enum KillArgs<'a> {
+    /* '-l' or '-L', with the signals to look up */
+    List(&'a [String]),
+    /* A signal, and the jobs and pids to send it to */
+    Send(libc::c_int, &'a [String]),
+}
+
+/* Parses the options of 'kill'. An error is the message to print and the
+ * status to return. */
+fn kill_args(mut args: &[String]) -> Result<KillArgs<'_>, (String, i32)> {
+    let mut sig = libc::SIGTERM;
+
+    if let Some(arg) = args.first() {
+        let spec = match arg.as_str() {
+            "-l" | "-L" => return Ok(KillArgs::List(&args[1..])),
+            "--" => None,
+            "-s" | "-n" => {
+                let spec = args.get(1).cloned();
+                args = &args[args.len().min(2)..];
+
+                if spec.is_none() {
+                    return Err((format!("{}: option requires an argument", arg), 2));
+                }
+                spec
+            },
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                args = &args[1..];
+                Some(arg[1..].to_string())
+            },
+            _ => None,
+        };
+
+        if let Some(spec) = spec {
+            sig = match signal::signal_number(&spec) {
+                Some(sig) => sig,
+                None => return Err((format!("{}: invalid signal specification", spec), 1)),
+            };
+        }
+    }
+
+    if args.first().map(|s| s.as_str()) == Some("--") {
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        return Err(("usage: kill [-s sigspec | -sigspec] [%job | pid]... or kill -l [sigspec]".to_string(), 2));
+    }
+
+    Ok(KillArgs::Send(sig, args))
+}
+
+/* 'kill [-s sig | -sig] [%job | pid]...' sends a signal, SIGTERM by default,
+ * to each job or process */
+fn builtin_kill(prog: &mut Prog) -> i32 {
+    let mut ret = 0;
+
+    let (sig, args) = match kill_args(&prog.args) {
+        Ok(KillArgs::Send(sig, args)) => (sig, args),
+        Ok(KillArgs::List(args)) => {
+            let args = args.to_vec();
+            return list_signals(prog, &args);
+        },
+        Err((err, status)) => {
+            fd_print(prog.stderr(), &format!("kill: {}\n", err));
+            return status;
+        },
+    };
 
     let job_list = JOB_LIST.lock().unwrap();
-    let real_job_id = job_id - 1;
 
-    if real_job_id >= job_list.list.len() {
-        fd_print(prog.stdout, &format!("fg: Unknown job {}\n", job_id));
-        return 1;
+    for arg in args {
+        let sent = if arg.starts_with('%') {
+            match job_list.find_job(arg) {
+                Ok(job) => job.lock().unwrap().signal(sig),
+                Err(err) => {
+                    fd_print(prog.stderr(), &format!("kill: {}\n", err));
+                    ret = 1;
+                    continue;
+                },
+            }
+        } else {
+            match arg.parse::<libc::pid_t>() {
+                Ok(pid) => unsafe { libc::kill(pid, sig) == 0 },
+                Err(_) => {
+                    fd_print(prog.stderr(), &format!("kill: {}: arguments must be process or job IDs\n", arg));
+                    ret = 1;
+                    continue;
+                },
+            }
+        };
+
+        if !sent {
+            fd_print(prog.stderr(), &format!("kill: ({}) - {}\n", arg, exec::strerror(exec::last_errno())));
+            ret = 1;
+        }
     }
 
-    let lck = job_list.list[real_job_id].clone();
-    let mut j = lck.lock().unwrap();
+    ret
+}
 
-    j.cont();
+/* The job a 'wait' operand refers to, along with the pid if it was given by
+ * one. A pid can also be of a job that has already finished. */
+fn wait_operand(job_list: &mut JobList, arg: &str) -> Result<(Arc<Mutex<Job>>, Option<libc::pid_t>), String> {
+    if arg.starts_with('%') {
+        return job_list.find_job(arg).map(|job| (job, None)).map_err(|err| err.to_string());
+    }
 
-    0
+    match arg.parse::<libc::pid_t>() {
+        Ok(pid) => {
+            let mut prog_index = 0;
+            job_list.find_pid(pid, &mut prog_index).0
+                .or_else(|| job_list.find_finished_pid(pid))
+                .map(|job| (job, Some(pid)))
+                .ok_or_else(|| format!("pid {} is not a child of this shell", pid))
+        },
+        Err(_) => Err(format!("`{}': not a pid or valid job spec", arg)),
+    }
+}
+
+/* 'wait [%job | pid]...' waits for each job, or the job with that process in
+ * it, and returns the status of the last one. Without arguments it waits for
+ * every job. 'wait -n' waits for whichever of the jobs finishes first. */
+fn builtin_wait(prog: &mut Prog) -> i32 {
+    wait_in(&JOB_LIST, prog)
+}
+
+fn wait_in(list: &Mutex<JobList>, prog: &mut Prog) -> i32 {
+    let mut args: &[String] = &prog.args;
+    let mut any = false;
+
+    if args.first().map(|s| s.as_str()) == Some("-n") {
+        any = true;
+        args = &args[1..];
+    }
+
+    /* Each job to wait for, with the pid it was given by, if any */
+    let mut jobs = Vec::new();
+    let mut ret = 0;
+
+    /* The job list is only locked to look up the jobs, and not while waiting
+     * for them */
+    let (all_jobs, catch) = {
+        let mut job_list = list.lock().unwrap();
+
+        /* In a pipeline, 'wait' runs in a forked copy of the shell */
+        job_list.forget_inherited();
+
+        for arg in args {
+            match wait_operand(&mut job_list, arg) {
+                Ok(job) => jobs.push(job),
+                Err(err) => {
+                    fd_print(prog.stderr(), &format!("wait: {}\n", err));
+                    ret = 127;
+                },
+            }
+        }
+
+        (job_list.list.clone(), job_list.job_control)
+    };
+
+    /* Ctrl-C stops the wait in an interactive shell */
+    if catch {
+        signal::catch_interrupt();
+    }
+
+    if any && !args.is_empty() && jobs.is_empty() {
+        /* None of the operands were jobs, so there is nothing to wait for */
+        ret = 127;
+    } else if any {
+        let jobs: Vec<Arc<Mutex<Job>>> = jobs.into_iter().map(|(job, _)| job).collect();
+
+        ret = wait_for_any(list, args.is_empty(), &jobs).unwrap_or(127);
+    } else if args.is_empty() {
+        for job in all_jobs {
+            wait_for_job(list, &job);
+
+            if signal::interrupted() {
+                break;
+            }
+        }
+
+        list.lock().unwrap().finished.clear();
+    } else {
+        for (job, pid) in jobs {
+            ret = wait_for_job(list, &job);
+
+            /* A pid gives the status of just that process */
+            if let Some(pid) = pid {
+                let j = job.lock().unwrap();
+
+                if let Some(status) = j.progs.iter().find(|p| p.pid == pid).and_then(|p| p.status) {
+                    ret = status.code();
+                }
+            }
+
+            if signal::interrupted() {
+                break;
+            }
+        }
+    }
+
+    if catch {
+        signal::ignore_interrupt();
+
+        if signal::interrupted() {
+            ret = 128 + libc::SIGINT;
+        }
+    }
+
+    ret
 }
 
-fn builtin_cd(prog: &mut Prog) -> usize {
+/* 'exit [n]' exits with the given status, or the status of the last command */
+fn builtin_exit(prog: &mut Prog) -> i32 {
+    let status = match prog.args.first() {
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                fd_print(prog.stderr(), &format!("exit: {}: numeric argument required\n", arg));
+                2
+            },
+        },
+        None => *LAST_STATUS.lock().unwrap(),
+    };
+
+    if prog.args.len() > 1 {
+        fd_print(prog.stderr(), "exit: too many arguments\n");
+        return 1;
+    }
+
+    exit_shell(status);
+
+    /* Only reached when there are stopped jobs */
+    1
+}
+
+fn builtin_cd(prog: &mut Prog) -> i32 {
     let mut new_cwd: String;
-    if prog.args.len() == 0 {
+    if prog.args.is_empty() {
         return 1;
     }
 
     let vec_dirs: Vec<&str> = prog.args[0].split("/").collect();
     let mut dirs: &[&str] = &vec_dirs;
 
-    if dirs[0] == "" {
+    if dirs[0].is_empty() {
         new_cwd = "/".to_string();
         dirs = &dirs[1..];
     } else {
@@ -144,7 +560,7 @@ fn builtin_cd(prog: &mut Prog) -> usize {
     }
 
     for dir in dirs {
-        if dir.len() == 0 {
+        if dir.is_empty() {
             continue;
         }
 
@@ -153,11 +569,9 @@ fn builtin_cd(prog: &mut Prog) -> usize {
                 continue;
             }
 
-            while new_cwd.pop().unwrap() != '/' {
-                ;
-            }
+            while new_cwd.pop().unwrap() != '/' { }
 
-            if new_cwd.len() == 0 {
+            if new_cwd.is_empty() {
                 new_cwd += "/";
             }
         } else if *dir != "." {
@@ -179,18 +593,365 @@ fn builtin_cd(prog: &mut Prog) -> usize {
         *CURRENT_DIR.lock().unwrap() = new_cwd;
         0
     } else {
-        fd_print(prog.stdout, &format!("cd {}: No such directory\n", prog.args[0]));
+        fd_print(prog.stdout(), &format!("cd {}: No such directory\n", prog.args[0]));
         1
     }
 }
 
-fn builtin_echo(prog: &mut Prog) -> usize {
-    if prog.args.len() == 0 {
+fn builtin_echo(prog: &mut Prog) -> i32 {
+    if prog.args.is_empty() {
         return 0;
     }
 
     let s = prog.args.join(" ") + "\n";
 
-    fd_print(prog.stdout, &s)
+    fd_print(prog.stdout(), &s);
+
+    0
+}
+
+
+fn builtin_export(prog: &mut Prog) -> i32 {
+    let mut vars = VARIABLES.lock().unwrap();
+    let mut ret = 0;
+
+    if prog.args.is_empty() || prog.args == ["-p"] {
+        for (name, value) in vars.environment() {
+            let value = value.replace('\\', "\\\\")
+                             .replace('"', "\\\"")
+                             .replace('$', "\\$")
+                             .replace('`', "\\`");
+
+            fd_print(prog.stdout(), &format!("export {}=\"{}\"\n", name, value));
+        }
+
+        return 0;
+    }
+
+    for arg in &prog.args {
+        let (name, value) = match arg.find('=') {
+            Some(eq) => (&arg[..eq], Some(&arg[eq + 1..])),
+            None => (arg.as_str(), None),
+        };
+
+        if !is_valid_name(name) {
+            fd_print(prog.stderr(), &format!("export: `{}': not a valid identifier\n", arg));
+            ret = 1;
+            continue;
+        }
+
+        if let Some(value) = value {
+            vars.set(name, value);
+        }
+
+        vars.export(name);
+    }
+
+    ret
 }
 
+/* 'env' lists the variables a command would be given, as 'NAME=value' lines.
+ * With a command to run, the env program is used instead. */
+fn builtin_env(prog: &mut Prog) -> i32 {
+    for (name, value) in prog.environment() {
+        fd_print(prog.stdout(), &format!("{}={}\n", name, value));
+    }
+
+    0
+}
+
+fn builtin_unset(prog: &mut Prog) -> i32 {
+    let mut vars = VARIABLES.lock().unwrap();
+    let mut ret = 0;
+
+    for name in &prog.args {
+        if !is_valid_name(name) {
+            fd_print(prog.stderr(), &format!("unset: `{}': not a valid identifier\n", name));
+            ret = 1;
+            continue;
+        }
+
+        vars.unset(name);
+    }
+
+    ret
+}
+
+/* 'set -o name' turns an option on and 'set +o name' turns it off, as do the
+ * single letter forms such as 'set -C'. Without a name, the options are
+ * listed. */
+fn builtin_set(prog: &mut Prog) -> i32 {
+    let mut options = OPTIONS.lock().unwrap();
+    let mut args = prog.args.iter();
+    let mut ret = 0;
+
+    while let Some(arg) = args.next() {
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => {
+                fd_print(prog.stderr(), &format!("set: {}: invalid option\n", arg));
+                return 2;
+            },
+        };
+
+        if arg[1..] != *"o" {
+            for c in arg[1..].chars() {
+                match OPTION_LETTERS.iter().find(|(letter, _)| *letter == c) {
+                    Some((_, name)) => { options.set(name, on); },
+                    None => {
+                        fd_print(prog.stderr(), &format!("set: {}{}: invalid option\n", &arg[..1], c));
+                        return 2;
+                    },
+                }
+            }
+
+            continue;
+        }
+
+        match args.next() {
+            Some(name) => {
+                if !options.set(name, on) {
+                    fd_print(prog.stderr(), &format!("set: {}: invalid option name\n", name));
+                    ret = 1;
+                }
+            },
+            None => {
+                for name in OPTION_NAMES {
+                    if on {
+                        fd_print(prog.stdout(), &format!("{:<15} {}\n", name, if options.is_set(name) { "on" } else { "off" }));
+                    } else {
+                        fd_print(prog.stdout(), &format!("set {}o {}\n", if options.is_set(name) { "-" } else { "+" }, name));
+                    }
+                }
+            },
+        }
+    }
+
+    ret
+}
+
+fn current_umask() -> libc::mode_t {
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask
+    }
+}
+
+/* Applies a symbolic mode like 'u=rwx,g+w,o-rwx' to a umask. The mode names
+ * the permissions to allow, which are the bits the umask leaves out. */
+fn symbolic_umask(mode: &str, mask: libc::mode_t) -> Option<libc::mode_t> {
+    let mut allowed = !mask & 0o777;
+
+    for clause in mode.split(',') {
+        let op_pos = clause.find(['=', '+', '-'])?;
+        let (who, rest) = clause.split_at(op_pos);
+
+        let mut who_bits = 0;
+        for c in who.chars() {
+            who_bits |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if who.is_empty() {
+            who_bits = 0o777;
+        }
+
+        let mut perms = 0;
+        for c in rest[1..].chars() {
+            perms |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return None,
+            };
+        }
+        perms &= who_bits;
+
+        match rest.chars().next() {
+            Some('=') => allowed = (allowed & !who_bits) | perms,
+            Some('+') => allowed |= perms,
+            _ => allowed &= !perms,
+        }
+    }
+
+    Some(!allowed & 0o777)
+}
+
+/* 'umask' prints the file creation mask, in octal or with '-S' as symbolic
+ * permissions, and 'umask mode' sets it */
+fn builtin_umask(prog: &mut Prog) -> i32 {
+    let mask = current_umask();
+    let mut args: &[String] = &prog.args;
+    let mut symbolic = false;
+
+    if args.first().map(|s| s.as_str()) == Some("-S") {
+        symbolic = true;
+        args = &args[1..];
+    }
+
+    let mode = match args.first() {
+        Some(mode) => mode,
+        None => {
+            if symbolic {
+                let allowed = !mask & 0o777;
+                let perms = |shift: u32| {
+                    let bits = (allowed >> shift) & 0o7;
+                    format!("{}{}{}",
+                            if bits & 0o4 != 0 { "r" } else { "" },
+                            if bits & 0o2 != 0 { "w" } else { "" },
+                            if bits & 0o1 != 0 { "x" } else { "" })
+                };
+
+                fd_print(prog.stdout(), &format!("u={},g={},o={}\n", perms(6), perms(3), perms(0)));
+            } else {
+                fd_print(prog.stdout(), &format!("{:04o}\n", mask));
+            }
+
+            return 0;
+        },
+    };
+
+    let new_mask = if mode.starts_with(|c: char| c.is_ascii_digit()) {
+        libc::mode_t::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o777)
+    } else {
+        symbolic_umask(mode, mask)
+    };
+
+    match new_mask {
+        Some(new_mask) => {
+            unsafe { libc::umask(new_mask); }
+            0
+        },
+        None => {
+            fd_print(prog.stderr(), &format!("umask: {}: invalid mode\n", mode));
+            1
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbolic_umasks() {
+        assert_eq!(symbolic_umask("u=rwx,g=rx,o=", 0o022), Some(0o027));
+        assert_eq!(symbolic_umask("g+w", 0o022), Some(0o002));
+        assert_eq!(symbolic_umask("o-r", 0o022), Some(0o026));
+        assert_eq!(symbolic_umask("a=r", 0o022), Some(0o333));
+        assert_eq!(symbolic_umask("=rx", 0o000), Some(0o222));
+        assert_eq!(symbolic_umask("ug-x", 0o000), Some(0o110));
+        assert_eq!(symbolic_umask("u=q", 0o022), None);
+        assert_eq!(symbolic_umask("z+r", 0o022), None);
+        assert_eq!(symbolic_umask("rwx", 0o022), None);
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn jobs_option_parsing() {
+        let args = strings(&["-l", "-rp", "%1", "-s"]);
+        let (options, rest) = jobs_options(&args).unwrap();
+        assert_eq!(options, JobsOptions { long: true, pgrps_only: true, state_only: Some(JobState::Running) });
+        assert_eq!(rest, &args[2..]);
+
+        let args = strings(&["-s", "--", "-l"]);
+        let (options, rest) = jobs_options(&args).unwrap();
+        assert_eq!(options, JobsOptions { long: false, pgrps_only: false, state_only: Some(JobState::Stopped) });
+        assert_eq!(rest, &args[2..]);
+
+        let args = strings(&["-", "%+"]);
+        assert_eq!(jobs_options(&args).unwrap(), (JobsOptions::default(), &args[..]));
+
+        assert_eq!(jobs_options(&strings(&["-lx"])), Err('x'));
+    }
+
+    #[test]
+    fn kill_arguments() {
+        let args = strings(&["%1", "123"]);
+        assert_eq!(kill_args(&args), Ok(KillArgs::Send(libc::SIGTERM, &args[..])));
+
+        let args = strings(&["-s", "hup", "%1"]);
+        assert_eq!(kill_args(&args), Ok(KillArgs::Send(libc::SIGHUP, &args[2..])));
+
+        let args = strings(&["-SIGKILL", "%+"]);
+        assert_eq!(kill_args(&args), Ok(KillArgs::Send(libc::SIGKILL, &args[1..])));
+
+        let args = strings(&["-9", "--", "-1"]);
+        assert_eq!(kill_args(&args), Ok(KillArgs::Send(libc::SIGKILL, &args[2..])));
+
+        let args = strings(&["--", "-1"]);
+        assert_eq!(kill_args(&args), Ok(KillArgs::Send(libc::SIGTERM, &args[1..])));
+
+        let args = strings(&["-l", "9"]);
+        assert_eq!(kill_args(&args), Ok(KillArgs::List(&args[1..])));
+
+        assert_eq!(kill_args(&strings(&["-s"])), Err(("-s: option requires an argument".to_string(), 2)));
+        assert_eq!(kill_args(&strings(&["-FOO", "1"])), Err(("FOO: invalid signal specification".to_string(), 1)));
+        assert_eq!(kill_args(&strings(&["-TERM"])).unwrap_err().1, 2);
+        assert_eq!(kill_args(&[]).unwrap_err().1, 2);
+    }
+
+    #[test]
+    fn kill_list_lookups() {
+        assert_eq!(signal_text("9"), Some("KILL".to_string()));
+        assert_eq!(signal_text("143"), Some("TERM".to_string()));
+        assert_eq!(signal_text("int"), Some(libc::SIGINT.to_string()));
+        assert_eq!(signal_text("SIGQUIT"), Some(libc::SIGQUIT.to_string()));
+        assert_eq!(signal_text("RTMIN+1"), Some((libc::SIGRTMIN() + 1).to_string()));
+        assert_eq!(signal_text("0"), None);
+        assert_eq!(signal_text("NOPE"), None);
+    }
+
+    #[test]
+    fn wait_operands() {
+        let mut job_list = JobList::new();
+        let mut job = Job::new();
+        let mut child = Prog::new();
+
+        child.pid = 100;
+        job.add_prog(child);
+        job.name = "sleep 10".to_string();
+        job.is_background = true;
+        job_list.add_job(job);
+
+        let mut find = |arg: &str| wait_operand(&mut job_list, arg).map(|(job, pid)| (job.lock().unwrap().id, pid));
+
+        assert_eq!(find("%1"), Ok((1, None)));
+        assert_eq!(find("%sleep"), Ok((1, None)));
+        assert_eq!(find("100"), Ok((1, Some(100))));
+        assert_eq!(find("%2"), Err("%2: no such job".to_string()));
+        assert_eq!(find("200"), Err("pid 200 is not a child of this shell".to_string()));
+        assert_eq!(find("x"), Err("`x': not a pid or valid job spec".to_string()));
+    }
+
+    #[test]
+    fn wait_without_jobs() {
+        let list = Mutex::new(JobList::new());
+        list.lock().unwrap().job_control = false;
+
+        let wait = |args: &[&str]| {
+            let mut prog = Prog::new();
+            prog.args = strings(args);
+            prog.add_redirect(libc::STDERR_FILENO, FdSource::Close);
+            wait_in(&list, &mut prog)
+        };
+
+        /* Nothing is waited for when none of the operands are jobs */
+        assert_eq!(wait(&["-n", "%99"]), 127);
+        assert_eq!(wait(&["-n", "x", "99999999"]), 127);
+        assert_eq!(wait(&["%99"]), 127);
+
+        /* Or when there are no jobs at all */
+        assert_eq!(wait(&["-n"]), 127);
+        assert_eq!(wait(&[]), 0);
+    }
+}
@@ -0,0 +1,664 @@
+use ast::*;
+use job::*;
+use prog::*;
+use builtin::*;
+use job_list::*;
+use expand::*;
+use lexer::*;
+use parser::*;
+use libc;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::fs;
+use ::*;
+
+pub fn run_list(list: &CommandList) -> i32 {
+    let mut status = 0;
+
+    for item in &list.items {
+        status = run_and_or(&item.and_or, item.is_background);
+    }
+
+    status
+}
+
+/* Parses and runs the whole of a script, as for 'rshell file' and '-c'. A
+ * syntax error anywhere stops it before anything runs. */
+pub fn run_source(name: &str, source: &str) -> i32 {
+    match Parser::new(InputLexer::new(source)).parse_program() {
+        Ok(list) => run_list(&list),
+        Err(err) => {
+            eprintln!("rshell: {}: {}", name, err);
+            2
+        },
+    }
+}
+
+pub fn run_and_or(and_or: &AndOr, is_background: bool) -> i32 {
+    /* A background list with more than one pipeline runs as a single job,
+     * inside of a subshell */
+    if is_background && !and_or.rest.is_empty() {
+        let mut list = CommandList::new();
+        list.items.push(ListItem { and_or: and_or.clone(), is_background: false });
+
+        let mut prog = Prog::new();
+        prog.name = and_or.to_string();
+        prog.body = Some(CompoundCommand::Subshell(list));
+
+        let mut job = Job::new();
+        job.name = and_or.to_string();
+        job.is_background = true;
+        job.add_prog(prog);
+
+        return job.run();
+    }
+
+    let mut status = run_pipeline(&and_or.first, is_background);
+
+    for &(op, ref pipeline) in &and_or.rest {
+        let should_run = match op {
+            AndOrOp::And => status == 0,
+            AndOrOp::Or => status != 0,
+        };
+
+        if should_run {
+            status = run_pipeline(pipeline, false);
+        }
+    }
+
+    status
+}
+
+pub fn run_pipeline(pipeline: &Pipeline, is_background: bool) -> i32 {
+    let mut progs: Vec<Prog> = Vec::new();
+
+    for cmd in &pipeline.commands {
+        match build_prog(cmd) {
+            Ok(prog) => progs.push(prog),
+            Err(err) => {
+                /* The rest of the pipeline still runs, with a prog that fails
+                 * in place of this one */
+                eprintln!("rshell: {}", err);
+
+                let mut prog = Prog::new();
+                prog.name = cmd.to_string();
+                prog.failed = true;
+                progs.push(prog);
+            },
+        }
+    }
+
+    let status = if !is_background && progs.len() == 1 && progs[0].failed {
+        1
+    } else if !is_background && progs.len() == 1 && runs_in_shell(&progs[0]) {
+        run_in_shell(progs.pop().unwrap())
+    } else {
+        build_job(pipeline, progs, is_background).run()
+    };
+
+    let status = if pipeline.negate && !is_background {
+        (status == 0) as i32
+    } else {
+        status
+    };
+
+    *LAST_STATUS.lock().unwrap() = status;
+
+    status
+}
+
+/* Runs a compound command in the current process. Subshells are only run this
+ * way once the shell has already been forked for them. */
+pub fn run_compound(compound: &CompoundCommand) -> i32 {
+    match *compound {
+        CompoundCommand::Subshell(ref list) | CompoundCommand::BraceGroup(ref list) => run_list(list),
+        CompoundCommand::If(ref branches, ref else_part) => {
+            for (cond, body) in branches {
+                if run_list(cond) == 0 {
+                    return run_list(body);
+                }
+            }
+
+            match *else_part {
+                Some(ref list) => run_list(list),
+                None => 0,
+            }
+        },
+        CompoundCommand::While(ref cond, ref body) => {
+            let mut status = 0;
+
+            while run_list(cond) == 0 {
+                status = run_list(body);
+            }
+
+            status
+        },
+        CompoundCommand::Until(ref cond, ref body) => {
+            let mut status = 0;
+
+            while run_list(cond) != 0 {
+                status = run_list(body);
+            }
+
+            status
+        },
+    }
+}
+
+/* Called in a forked child that is going to run shell commands itself. The
+ * child gets a fresh job list, and doesn't do job control of its own. */
+pub fn enter_subshell() {
+    let mut job_list = JOB_LIST.lock().unwrap();
+
+    *job_list = JobList::new();
+    job_list.job_control = false;
+}
+
+/* Builtins and compound commands other than subshells don't need a fork when
+ * they run in the forground */
+fn runs_in_shell(prog: &Prog) -> bool {
+    match prog.body {
+        Some(CompoundCommand::Subshell(_)) => false,
+        Some(_) => true,
+        None => prog.builtin.is_some() || prog.file.is_empty(),
+    }
+}
+
+fn run_in_shell(mut prog: Prog) -> i32 {
+    match prog.body.take() {
+        None => {
+            if let Some(fd) = prog.bad_dup() {
+                eprintln!("rshell: {}: Bad file descriptor", fd);
+                prog.close_fds();
+                return 1;
+            }
+
+            /* A command that is only assignments sets shell variables, and
+             * gives the status of its last command substitution */
+            if prog.file.is_empty() {
+                let mut vars = VARIABLES.lock().unwrap();
+
+                for (name, value) in &prog.assignments {
+                    vars.set(name, value);
+                }
+
+                prog.close_fds();
+                return prog.subst_status.unwrap_or(0);
+            }
+
+            let ret = prog.run_builtin();
+
+            /* 'fg' leaves behind a forground job to wait on */
+            let forground_job = JOB_LIST.lock().unwrap().forground_job.clone();
+            if let Some(job) = forground_job {
+                JOB_LIST.lock().unwrap().update_job_list();
+                let status = job.lock().unwrap().status;
+                return status;
+            }
+
+            ret
+        },
+        Some(compound) => {
+            let saved = match unsafe { redirect_shell_fds(&mut prog) } {
+                Some(saved) => saved,
+                None => return 1,
+            };
+
+            let ret = run_compound(&compound);
+
+            unsafe { restore_shell_fds(saved); }
+
+            ret
+        },
+    }
+}
+
+/* Applies a prog's redirections to the shell's own fds, returning saved copies
+ * of the fds it changed for restore_shell_fds(). A saved copy of -1 means the
+ * fd was closed to begin with. */
+unsafe fn redirect_shell_fds(prog: &mut Prog) -> Option<Vec<(libc::c_int, libc::c_int)>> {
+    let mut saved: Vec<(libc::c_int, libc::c_int)> = Vec::new();
+    let mut ok = true;
+
+    for redirect in &prog.redirects {
+        if !saved.iter().any(|&(fd, _)| fd == redirect.fd) {
+            saved.push((redirect.fd, libc::fcntl(redirect.fd, libc::F_DUPFD_CLOEXEC, 10)));
+        }
+
+        if !redirect.apply() {
+            if let FdSource::Dup(src) = redirect.source {
+                eprintln!("rshell: {}: Bad file descriptor", src);
+            }
+            ok = false;
+            break;
+        }
+    }
+
+    prog.close_fds();
+
+    if ok {
+        Some(saved)
+    } else {
+        restore_shell_fds(saved);
+        None
+    }
+}
+
+unsafe fn restore_shell_fds(saved: Vec<(libc::c_int, libc::c_int)>) {
+    for (fd, copy) in saved.into_iter().rev() {
+        if copy == -1 {
+            libc::close(fd);
+        } else {
+            libc::dup2(copy, fd);
+            libc::close(copy);
+        }
+    }
+}
+
+/* Connects the progs of a pipeline together into a job */
+fn build_job(pipeline: &Pipeline, progs: Vec<Prog>, is_background: bool) -> Job {
+    let mut job = Job::new();
+    let mut next_stdin = -1;
+    let prog_count = progs.len();
+
+    job.name = pipeline.to_string();
+    job.is_background = is_background;
+
+    for (i, mut prog) in progs.into_iter().enumerate() {
+        /* The pipes are set up first, so that redirections on the command
+         * take priority over them */
+        let mut pipes = Vec::new();
+
+        if next_stdin != -1 {
+            pipes.push(FdRedirect { fd: libc::STDIN_FILENO, source: FdSource::File(next_stdin) });
+            next_stdin = -1;
+        }
+
+        if i + 1 != prog_count {
+            let (read_fd, write_fd) = make_pipe();
+
+            pipes.push(FdRedirect { fd: libc::STDOUT_FILENO, source: FdSource::File(write_fd) });
+            next_stdin = read_fd;
+        }
+
+        prog.redirects.splice(0..0, pipes);
+        job.add_prog(prog);
+    }
+
+    job
+}
+
+/* Expands a command and opens its redirections */
+fn build_prog(cmd: &Command) -> Result<Prog, ExpandError> {
+    let mut prog = Prog::new();
+    prog.name = cmd.to_string();
+
+    let result = match *cmd {
+        Command::Simple(ref simple) => {
+            let fields = expand_words(&simple.words)?;
+
+            if let Some((file, args)) = fields.split_first() {
+                prog.file = file.clone();
+                prog.builtin = builtin_find_callback(&prog.file);
+
+                /* Only the listing of 'env' is built in */
+                if prog.file == "env" && !args.is_empty() {
+                    prog.builtin = None;
+                }
+
+                for arg in args {
+                    prog.add_arg(arg);
+                }
+            }
+
+            /* Each assignment can see the ones before it, so they are set
+             * while the rest are expanded, and then put back */
+            let mut saved = Vec::new();
+
+            for assignment in &simple.assignments {
+                let value = match expand_word(&assignment.value) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        restore_vars(saved);
+                        return Err(err);
+                    },
+                };
+                let mut vars = VARIABLES.lock().unwrap();
+
+                saved.push((assignment.name.clone(), vars.get(&assignment.name).map(|s| s.to_string())));
+                vars.set(&assignment.name, &value);
+                prog.assignments.push((assignment.name.clone(), value));
+            }
+
+            restore_vars(saved);
+
+            let result = open_redirects(&mut prog, &simple.redirects);
+
+            let has_subst = simple.words.iter()
+                .chain(simple.assignments.iter().map(|a| &a.value))
+                .chain(simple.redirects.iter().map(|r| &r.target))
+                .any(|word| word.has_command_subst());
+
+            if prog.file.is_empty() && has_subst {
+                prog.subst_status = Some(*LAST_STATUS.lock().unwrap());
+            }
+
+            result
+        },
+        Command::Compound(ref compound, ref redirects) => {
+            prog.body = Some(compound.clone());
+            open_redirects(&mut prog, redirects)
+        },
+    };
+
+    /* The files opened before a failed redirection are closed again */
+    match result {
+        Ok(()) => Ok(prog),
+        Err(err) => {
+            prog.close_fds();
+            Err(err)
+        },
+    }
+}
+
+/* Puts back the variables that were set while expanding assignments */
+fn restore_vars(saved: Vec<(String, Option<String>)>) {
+    let mut vars = VARIABLES.lock().unwrap();
+
+    for (name, old_value) in saved.into_iter().rev() {
+        match old_value {
+            Some(value) => vars.set(&name, &value),
+            None => vars.unset(&name),
+        }
+    }
+}
+
+pub fn last_errno() -> i32 {
+    io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/* The message for an errno value, such as 'No such file or directory' */
+pub fn strerror(errno: i32) -> String {
+    unsafe { CStr::from_ptr(libc::strerror(errno)).to_string_lossy().into_owned() }
+}
+
+/* Opens a file for a redirection. The shell keeps it at fd 10 or above and
+ * away from 'target', so it won't get in the way of the fds the prog uses.
+ * New files get mode 0666, less the umask. */
+fn open_file(path: &str, flags: libc::c_int, target: libc::c_int) -> libc::c_int {
+    let path = CString::new(path).unwrap();
+
+    unsafe {
+        let fd = libc::open(path.as_ptr(), flags | libc::O_CLOEXEC, 0o666);
+
+        if fd == -1 || (fd >= 10 && fd != target) {
+            return fd;
+        }
+
+        let moved = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10);
+        libc::close(fd);
+        moved
+    }
+}
+
+/* The flags for opening the target of a '>', which with 'noclobber' set
+ * refuses to overwrite an existing regular file. Other files such as
+ * /dev/null can still be written to, and new files are created with O_EXCL so
+ * that one can't appear in between. */
+fn truncate_flags(path: &str, noclobber: bool) -> Result<libc::c_int, ExpandError> {
+    if !noclobber {
+        return Ok(libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC);
+    }
+
+    match fs::metadata(path) {
+        Ok(ref metadata) if metadata.is_file() => Err(ExpandError::Clobber(path.to_string())),
+        Ok(_) => Ok(libc::O_WRONLY),
+        Err(_) => Ok(libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL),
+    }
+}
+
+/* Gives the body of a here-document as a file to read from. It's written to
+ * a temporary file which is removed right away, so that a body of any size
+ * can be written before the prog starts reading it. */
+fn here_doc_fd(body: &str) -> libc::c_int {
+    let dir = VARIABLES.lock().unwrap().get("TMPDIR").unwrap_or("/tmp").to_string();
+    let template = CString::new(format!("{}/rshell-XXXXXX", dir)).unwrap();
+    let mut template = template.into_bytes_with_nul();
+
+    unsafe {
+        let fd = libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char);
+        if fd == -1 {
+            return -1;
+        }
+
+        libc::unlink(template.as_ptr() as *const libc::c_char);
+
+        let mut written = 0;
+        while written < body.len() {
+            let len = libc::write(fd, body[written..].as_ptr() as *const libc::c_void, body.len() - written);
+            if len <= 0 {
+                break;
+            }
+            written += len as usize;
+        }
+
+        libc::lseek(fd, 0, libc::SEEK_SET);
+
+        let moved = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10);
+        libc::close(fd);
+        moved
+    }
+}
+
+fn open_redirects(prog: &mut Prog, redirects: &[Redirect]) -> Result<(), ExpandError> {
+    let noclobber = OPTIONS.lock().unwrap().is_set("noclobber");
+
+    for redirect in redirects {
+        let target = expand_word(&redirect.target)?;
+        let fd = redirect.fd.unwrap_or(redirect.kind.default_fd());
+
+        let flags = match redirect.kind {
+            RedirectKind::HereDoc(_) | RedirectKind::HereString => {
+                let body = if redirect.kind == RedirectKind::HereString { target + "\n" } else { target };
+                let src = here_doc_fd(&body);
+
+                if src == -1 {
+                    return Err(ExpandError::HereDoc(last_errno()));
+                }
+
+                prog.add_redirect(fd, FdSource::File(src));
+                continue;
+            },
+            RedirectKind::In => libc::O_RDONLY,
+            RedirectKind::Out | RedirectKind::AllOut => truncate_flags(&target, noclobber)?,
+            RedirectKind::AppendOut | RedirectKind::AllAppendOut => libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND,
+            RedirectKind::Clobber => libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+            RedirectKind::ReadWrite => libc::O_RDWR | libc::O_CREAT,
+            RedirectKind::DupIn | RedirectKind::DupOut => {
+                if target == "-" {
+                    prog.add_redirect(fd, FdSource::Close);
+                    continue;
+                }
+
+                if let Ok(src) = target.parse::<libc::c_int>() {
+                    prog.add_redirect(fd, FdSource::Dup(src));
+                    continue;
+                }
+
+                /* '>&file' without an fd number is the same as '&>file' */
+                if redirect.kind == RedirectKind::DupIn || redirect.fd.is_some() {
+                    return Err(ExpandError::AmbiguousRedirect(target));
+                }
+
+                truncate_flags(&target, noclobber)?
+            },
+        };
+
+        let src = open_file(&target, flags, fd);
+
+        if src == -1 {
+            return Err(ExpandError::Open(target, last_errno()));
+        }
+
+        prog.add_redirect(fd, FdSource::File(src));
+
+        match redirect.kind {
+            RedirectKind::AllOut | RedirectKind::AllAppendOut | RedirectKind::DupOut => {
+                prog.add_redirect(libc::STDERR_FILENO, FdSource::Dup(libc::STDOUT_FILENO));
+            },
+            _ => { },
+        }
+    }
+
+    Ok(())
+}
+
+/* Runs a list of commands for a command substitution, as a forground job
+ * whose output goes to a pipe read by the shell */
+pub fn command_output(list: &CommandList) -> String {
+    let (read_fd, write_fd) = make_pipe();
+
+    /* The subshell isn't a job of its own: it is waited for here, and signals
+     * from the terminal reach it through the shell's process group */
+    let mut prog = Prog::new();
+    prog.name = list.to_string();
+    prog.body = Some(CompoundCommand::Subshell(list.clone()));
+    prog.is_command_subst = true;
+    prog.add_redirect(libc::STDOUT_FILENO, FdSource::File(write_fd));
+    prog.run();
+
+    /* The write end is still open here if the fork failed */
+    prog.close_fds();
+
+    let mut output = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let len = unsafe { libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+
+        if len > 0 {
+            output.extend_from_slice(&buf[..len as usize]);
+        } else if len == 0 || io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            break;
+        }
+    }
+
+    unsafe { libc::close(read_fd); }
+
+    let mut status = 1;
+
+    if prog.pid != -1 {
+        let mut wstatus: libc::c_int = 0;
+
+        loop {
+            if unsafe { libc::waitpid(prog.pid, &mut wstatus as *mut libc::c_int, 0) } != -1 {
+                status = ExitStatus::from_wait(wstatus).code();
+                break;
+            } else if last_errno() != libc::EINTR {
+                break;
+            }
+        }
+    }
+
+    *LAST_STATUS.lock().unwrap() = status;
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+fn make_pipe() -> (libc::c_int, libc::c_int) {
+    let mut pipefd: [libc::c_int; 2] = [0, 2];
+
+    unsafe {
+        libc::pipe(&mut pipefd[0] as *mut libc::c_int);
+
+        libc::fcntl(pipefd[0], libc::F_SETFD, libc::fcntl(pipefd[0], libc::F_GETFD) | libc::FD_CLOEXEC);
+        libc::fcntl(pipefd[1], libc::F_SETFD, libc::fcntl(pipefd[1], libc::F_GETFD) | libc::FD_CLOEXEC);
+    }
+
+    (pipefd[0], pipefd[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A path in a fresh temporary directory for the named test */
+    fn temp_path(test: &str, name: &str) -> String {
+        let dir = format!("{}/rshell-exec-{}-{}", std::env::temp_dir().display(), std::process::id(), test);
+        fs::create_dir_all(&dir).unwrap();
+        format!("{}/{}", dir, name)
+    }
+
+    fn try_build(s: &str) -> Result<Prog, ExpandError> {
+        let list = Parser::new(InputLexer::new(s)).parse_program().unwrap();
+        build_prog(&list.items[0].and_or.first.commands[0])
+    }
+
+    fn build(s: &str) -> Prog {
+        try_build(s).unwrap()
+    }
+
+    #[test]
+    fn redirection_order() {
+        let out = |name: &str| temp_path("order", name);
+
+        /* The file is opened once, and both fds end up on it */
+        let mut prog = build(&format!("cmd >{} 2>&1", out("1")));
+        let file = prog.stdout();
+        assert!(file >= 10);
+        assert_eq!(prog.stderr(), file);
+        prog.close_fds();
+
+        /* stderr copies stdout from before it was redirected */
+        let mut prog = build(&format!("cmd 2>&1 >{}", out("2")));
+        assert!(prog.stdout() >= 10);
+        assert_eq!(prog.stderr(), libc::STDOUT_FILENO);
+        prog.close_fds();
+
+        let mut prog = build(&format!("cmd 3>{} 1>&3 3>&-", out("3")));
+        assert!(prog.stdout() >= 10);
+        assert_eq!(prog.fd(3), -1);
+        assert_eq!(prog.bad_dup(), None);
+        prog.close_fds();
+
+        let mut prog = build(&format!("cmd &>{}", out("4")));
+        assert_eq!(prog.stderr(), prog.stdout());
+        prog.close_fds();
+
+        let prog = build("cmd 2>&1 >&- 4<&1");
+        assert_eq!(prog.stdout(), -1);
+        assert_eq!(prog.stderr(), libc::STDOUT_FILENO);
+        assert_eq!(prog.bad_dup(), Some(1));
+
+        fs::remove_dir_all(std::path::Path::new(&out("1")).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn redirection_failures() {
+        let error = |s: &str| try_build(s).unwrap_err().to_string();
+        let dir = temp_path("failures", "");
+
+        assert_eq!(error("cat </nonexistent-rshell/in"), "/nonexistent-rshell/in: No such file or directory");
+        assert_eq!(error(&format!("cat >{}", dir)), format!("{}: Is a directory", dir));
+        assert_eq!(error(&format!("cat >{}out </nonexistent-rshell/in", dir)), "/nonexistent-rshell/in: No such file or directory");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn noclobber_flags() {
+        let existing = temp_path("noclobber", "existing");
+        let missing = temp_path("noclobber", "missing");
+        fs::write(&existing, "").unwrap();
+
+        assert_eq!(truncate_flags(&existing, false).unwrap(), libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC);
+        match truncate_flags(&existing, true) {
+            Err(ExpandError::Clobber(ref path)) => assert_eq!(path, &existing),
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(truncate_flags(&missing, true).unwrap(), libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL);
+        assert_eq!(truncate_flags("/dev/null", true).unwrap(), libc::O_WRONLY);
+
+        fs::remove_dir_all(std::path::Path::new(&existing).parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,371 @@
+use ast::*;
+use glob;
+use arith;
+use exec;
+use lexer::*;
+use parser::*;
+use std::fmt;
+use ::*;
+
+const DEFAULT_IFS: &str = " \t\n";
+
+/* A name that can go inside of '${...}': a variable name, a positional
+ * parameter number, or a special parameter */
+fn is_param_name(name: &str) -> bool {
+    is_valid_name(name)
+        || (!name.is_empty() && name.chars().all(|c| c.is_ascii_digit()))
+        || (name.len() == 1 && SPECIAL_PARAMS.contains(name))
+}
+
+fn param_value(name: &str) -> Result<String, ExpandError> {
+    if !is_param_name(name) {
+        return Err(ExpandError::BadSubst(name.to_string()));
+    }
+
+    let value = match name {
+        "?" => LAST_STATUS.lock().unwrap().to_string(),
+        "$" => SHELL_PID.lock().unwrap().to_string(),
+        "0" => SHELL_NAME.lock().unwrap().clone(),
+        "!" => {
+            let pid = *LAST_BACKGROUND_PID.lock().unwrap();
+
+            if pid == -1 {
+                String::new()
+            } else {
+                pid.to_string()
+            }
+        },
+        "#" | "@" | "*" => {
+            /* '$*' joins them with the first character of IFS, or nothing if
+             * IFS is set but empty */
+            let sep = match VARIABLES.lock().unwrap().get("IFS") {
+                Some(ifs) => ifs.chars().next().map(|c| c.to_string()).unwrap_or_default(),
+                None => " ".to_string(),
+            };
+
+            positional_value(name, &POSITIONAL_PARAMS.lock().unwrap(), &sep)
+        },
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            positional_value(name, &POSITIONAL_PARAMS.lock().unwrap(), " ")
+        },
+        _ => VARIABLES.lock().unwrap().get(name).unwrap_or("").to_string(),
+    };
+
+    Ok(value)
+}
+
+/* The value of '$#', '$@', '$*' or a numbered parameter such as '$1', from
+ * the positional parameters 'params'. 'sep' is what '$*' joins them with. */
+fn positional_value(name: &str, params: &[String], sep: &str) -> String {
+    match name {
+        "#" => params.len().to_string(),
+        "@" => params.join(" "),
+        "*" => params.join(sep),
+        _ => match name.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= params.len() => params[n - 1].clone(),
+            _ => String::new(),
+        },
+    }
+}
+
+#[derive(Debug)]
+pub enum ExpandError {
+    /* A pattern that matched nothing with 'failglob' set */
+    NoMatch(String),
+    /* A command substitution that failed to parse */
+    Syntax(ParseError),
+    /* A '<&' or '>&' redirection whose target isn't an fd number */
+    AmbiguousRedirect(String),
+    /* A redirection target that couldn't be opened, with the errno */
+    Open(String, i32),
+    /* The temporary file for a here-document couldn't be made */
+    HereDoc(i32),
+    /* A '>' that would overwrite a file with 'noclobber' set */
+    Clobber(String),
+    /* An arithmetic expression and the error evaluating it */
+    Arith(String, arith::ArithError),
+    /* A '${...}' whose contents aren't a parameter name */
+    BadSubst(String),
+}
+
+impl fmt::Display for ExpandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExpandError::NoMatch(ref pattern) => write!(f, "no match: {}", pattern),
+            ExpandError::Syntax(ref err) => write!(f, "{}", err),
+            ExpandError::AmbiguousRedirect(ref target) => write!(f, "{}: ambiguous redirect", target),
+            ExpandError::Open(ref path, errno) => write!(f, "{}: {}", path, exec::strerror(errno)),
+            ExpandError::HereDoc(errno) => write!(f, "cannot create temp file for here-document: {}", exec::strerror(errno)),
+            ExpandError::Clobber(ref path) => write!(f, "{}: cannot overwrite existing file", path),
+            ExpandError::Arith(ref expr, ref err) => write!(f, "{}: {}", expr.trim(), err),
+            ExpandError::BadSubst(ref name) => write!(f, "${{{}}}: bad substitution", name),
+        }
+    }
+}
+
+/* Runs the commands of a command substitution, giving their output without
+ * the trailing newlines */
+fn command_subst(source: &str) -> Result<String, ExpandError> {
+    let list = match Parser::new(InputLexer::new(source)).parse_program() {
+        Ok(list) => list,
+        Err(err) => return Err(ExpandError::Syntax(err)),
+    };
+
+    let mut output = exec::command_output(&list);
+
+    while output.ends_with('\n') {
+        output.pop();
+    }
+
+    Ok(output)
+}
+
+fn arith_value(expr: &[WordPart]) -> Result<String, ExpandError> {
+    let expr = expand_parts(expr)?;
+
+    match arith::eval(&expr) {
+        Ok(value) => Ok(value.to_string()),
+        Err(err) => Err(ExpandError::Arith(expr, err)),
+    }
+}
+
+/* A field along with the pattern it is globbed with, where the characters
+ * that came from quotes are escaped */
+struct Field {
+    text: String,
+    pattern: String,
+}
+
+/* The expansion of a list of words, split into fields */
+struct Fields {
+    fields: Vec<Field>,
+    current: String,
+    current_pattern: String,
+    /* Set once the current field exists, even if it's empty, such as after a
+     * quoted empty string */
+    has_current: bool,
+    ifs: String,
+}
+
+impl Fields {
+    fn new() -> Fields {
+        let ifs = match VARIABLES.lock().unwrap().get("IFS") {
+            Some(ifs) => ifs.to_string(),
+            None => DEFAULT_IFS.to_string(),
+        };
+
+        Fields {
+            fields: Vec::new(),
+            current: String::new(),
+            current_pattern: String::new(),
+            has_current: false,
+            ifs,
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.current_pattern.push_str(s);
+        self.has_current = true;
+    }
+
+    fn push_quoted(&mut self, s: &str) {
+        self.current.push_str(s);
+        self.current_pattern.push_str(&glob::escape(s));
+        self.has_current = true;
+    }
+
+    /* Adds the result of an unquoted expansion, splitting it on the characters
+     * in IFS. Runs of IFS whitespace act as a single separator. */
+    fn push_split(&mut self, s: &str) {
+        for c in s.chars() {
+            if !self.ifs.contains(c) {
+                self.current.push(c);
+                self.current_pattern.push(c);
+                self.has_current = true;
+            } else if self.has_current {
+                self.end_field();
+            } else if !c.is_whitespace() {
+                self.fields.push(Field { text: String::new(), pattern: String::new() });
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        if self.has_current {
+            self.fields.push(Field { text: self.current.clone(), pattern: self.current_pattern.clone() });
+            self.current.clear();
+            self.current_pattern.clear();
+            self.has_current = false;
+        }
+    }
+
+    /* A '"$@"' gives each positional parameter as a field of its own, with
+     * the text before it joined to the first and the text after it joined to
+     * the last. With no positional parameters it gives nothing at all. */
+    fn add_double_quoted(&mut self, parts: &[WordPart]) -> Result<(), ExpandError> {
+        let mut text = String::new();
+        let mut quoted = false;
+
+        for part in parts {
+            if *part != WordPart::Param("@".to_string()) {
+                text += &expand_parts(std::slice::from_ref(part))?;
+                quoted = true;
+                continue;
+            }
+
+            let params = POSITIONAL_PARAMS.lock().unwrap().clone();
+
+            for (i, param) in params.iter().enumerate() {
+                if i == 0 {
+                    self.push_quoted(&text);
+                    text.clear();
+                } else {
+                    self.end_field();
+                }
+
+                self.push_quoted(param);
+            }
+        }
+
+        if quoted || parts.is_empty() {
+            self.push_quoted(&text);
+        }
+
+        Ok(())
+    }
+
+    fn add_word(&mut self, word: &Word) -> Result<(), ExpandError> {
+        for part in &word.parts {
+            match *part {
+                WordPart::Literal(ref s) => self.push_str(s),
+                WordPart::SingleQuoted(ref s) => self.push_quoted(s),
+                WordPart::Escaped(c) => self.push_quoted(&c.to_string()),
+                WordPart::DoubleQuoted(ref inner) => self.add_double_quoted(inner)?,
+                WordPart::Param(ref name) => {
+                    let value = param_value(name)?;
+                    self.push_split(&value);
+                },
+                WordPart::CommandSubst(ref source) => {
+                    let output = command_subst(source)?;
+                    self.push_split(&output);
+                },
+                WordPart::Arith(ref expr) => {
+                    let value = arith_value(expr)?;
+                    self.push_split(&value);
+                },
+            }
+        }
+
+        self.end_field();
+        Ok(())
+    }
+}
+
+fn expand_parts(parts: &[WordPart]) -> Result<String, ExpandError> {
+    let mut s = String::new();
+
+    for part in parts {
+        match *part {
+            WordPart::Literal(ref lit) | WordPart::SingleQuoted(ref lit) => s += lit,
+            WordPart::Escaped(c) => s.push(c),
+            WordPart::DoubleQuoted(ref inner) => s += &expand_parts(inner)?,
+            WordPart::Param(ref name) => s += &param_value(name)?,
+            WordPart::CommandSubst(ref source) => s += &command_subst(source)?,
+            WordPart::Arith(ref expr) => s += &arith_value(expr)?,
+        }
+    }
+
+    Ok(s)
+}
+
+/* Expands a word without field splitting, as is done for assignments and
+ * redirection targets */
+pub fn expand_word(word: &Word) -> Result<String, ExpandError> {
+    expand_parts(&word.parts)
+}
+
+/* Expands the words of a command into its fields, replacing the fields that
+ * are patterns with the paths they match */
+pub fn expand_words(words: &[Word]) -> Result<Vec<String>, ExpandError> {
+    let mut fields = Fields::new();
+
+    for word in words {
+        fields.add_word(word)?;
+    }
+
+    glob_fields(fields.fields, &OPTIONS.lock().unwrap())
+}
+
+/* Replaces the fields that are patterns with the paths they match, with
+ * 'nullglob', 'failglob' and 'globstar' as they are in 'options' */
+fn glob_fields(fields: Vec<Field>, options: &ShellOptions) -> Result<Vec<String>, ExpandError> {
+    let mut expanded = Vec::new();
+
+    for field in fields {
+        if !glob::has_glob_chars(&field.pattern) {
+            expanded.push(field.text);
+            continue;
+        }
+
+        let paths = glob::glob(&field.pattern, options.is_set("globstar"));
+
+        if !paths.is_empty() {
+            expanded.extend(paths);
+        } else if options.is_set("failglob") {
+            return Err(ExpandError::NoMatch(field.text));
+        } else if !options.is_set("nullglob") {
+            expanded.push(field.text);
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Expands literal words, globbing them with 'options' */
+    fn glob_words(words: &[&str], options: &ShellOptions) -> Result<Vec<String>, ExpandError> {
+        let mut fields = Fields::new();
+
+        for w in words {
+            fields.add_word(&Word { parts: vec![WordPart::Literal(w.to_string())] })?;
+        }
+
+        glob_fields(fields.fields, options)
+    }
+
+    #[test]
+    fn positional_parameters() {
+        let params: Vec<String> = ["a", "b c", "d"].iter().map(|s| s.to_string()).collect();
+
+        assert_eq!(positional_value("#", &params, " "), "3");
+        assert_eq!(positional_value("1", &params, " "), "a");
+        assert_eq!(positional_value("2", &params, " "), "b c");
+        assert_eq!(positional_value("4", &params, " "), "");
+        assert_eq!(positional_value("0", &params, " "), "");
+        assert_eq!(positional_value("@", &params, ":"), "a b c d");
+        assert_eq!(positional_value("*", &params, ":"), "a:b c:d");
+        assert_eq!(positional_value("#", &[], " "), "0");
+    }
+
+    #[test]
+    fn patterns_without_matches() {
+        let pattern = "/nonexistent-rshell-dir/*.c";
+        let words = ["ls", pattern];
+        let mut options = ShellOptions::new();
+
+        assert_eq!(glob_words(&words, &options).unwrap(), vec!["ls", pattern]);
+
+        options.set("nullglob", true);
+        assert_eq!(glob_words(&words, &options).unwrap(), vec!["ls"]);
+
+        options.set("failglob", true);
+        match glob_words(&words, &options) {
+            Err(ExpandError::NoMatch(ref text)) => assert_eq!(text, pattern),
+            result => panic!("{:?}", result),
+        }
+    }
+}
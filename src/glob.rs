@@ -0,0 +1,338 @@
+use std::fs;
+use ::*;
+
+/* Characters with a special meaning in a pattern */
+const GLOB_CHARS: &str = "*?[]\\";
+
+/* Quotes the special characters of a string, so that it only matches itself
+ * when used in a pattern */
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in s.chars() {
+        if GLOB_CHARS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn unescape(s: &str) -> String {
+    let mut chars = s.chars();
+    let mut unescaped = String::new();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(c) = chars.next() {
+                unescaped.push(c);
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+
+    unescaped
+}
+
+/* Whether a pattern has any unquoted '*', '?' or '[' in it */
+pub fn has_glob_chars(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '*' | '?' | '[' => return true,
+            _ => { },
+        }
+    }
+
+    false
+}
+
+/* Matches 'c' against the bracket expression starting after a '['. Returns
+ * whether it matched and the length of the expression, or None if there's no
+ * closing ']' and the '[' is just a normal character. */
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let mut negate = false;
+    let mut matched = false;
+
+    if i < pattern.len() && (pattern[i] == '!' || pattern[i] == '^') {
+        negate = true;
+        i += 1;
+    }
+
+    let start = i;
+
+    loop {
+        if i >= pattern.len() {
+            return None;
+        }
+
+        /* A ']' right at the start is part of the set */
+        if pattern[i] == ']' && i != start {
+            return Some((matched != negate, i + 1));
+        }
+
+        let mut low = pattern[i];
+        if low == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            low = pattern[i];
+        }
+        i += 1;
+
+        let mut high = low;
+        if i + 1 < pattern.len() && pattern[i] == '-' && pattern[i + 1] != ']' {
+            high = pattern[i + 1];
+            if high == '\\' && i + 2 < pattern.len() {
+                i += 1;
+                high = pattern[i + 1];
+            }
+            i += 2;
+        }
+
+        if low <= c && c <= high {
+            matched = true;
+        }
+    }
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => {
+            (0..name.len() + 1).any(|skip| match_chars(&pattern[1..], &name[skip..]))
+        },
+        Some('?') => !name.is_empty() && match_chars(&pattern[1..], &name[1..]),
+        Some('[') if !name.is_empty() => {
+            match match_bracket(&pattern[1..], name[0]) {
+                Some((true, len)) => match_chars(&pattern[len + 1..], &name[1..]),
+                Some((false, _)) => false,
+                None => name[0] == '[' && match_chars(&pattern[1..], &name[1..]),
+            }
+        },
+        Some('\\') if pattern.len() > 1 => {
+            !name.is_empty() && name[0] == pattern[1] && match_chars(&pattern[2..], &name[1..])
+        },
+        Some(c) => !name.is_empty() && name[0] == *c && match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/* Matches a single file name against a pattern. Names starting with a '.'
+ * are only matched when the pattern starts with one too. */
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') && !pattern.starts_with("\\.") {
+        return false;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    match_chars(&pattern, &name)
+}
+
+/* Globbed paths are relative to the shell's current directory */
+fn real_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", *CURRENT_DIR.lock().unwrap(), path)
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    fs::metadata(real_path(path)).map(|m| m.is_dir()).unwrap_or(false)
+}
+
+fn exists(path: &str) -> bool {
+    fs::symlink_metadata(real_path(path)).is_ok()
+}
+
+/* The names in a directory, without '.' and '..' */
+fn dir_entries(dir: &str) -> Vec<String> {
+    let entries = match fs::read_dir(real_path(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries.filter_map(|entry| entry.ok())
+           .filter_map(|entry| entry.file_name().into_string().ok())
+           .collect()
+}
+
+/* Every path below 'dir' that isn't hidden, for '**'. Symbolic links to
+ * directories aren't followed. */
+fn walk(dir: &str, paths: &mut Vec<String>) {
+    for name in dir_entries(dir) {
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let path = format!("{}{}", dir, name);
+        let is_real_dir = fs::symlink_metadata(real_path(&path)).map(|m| m.is_dir()).unwrap_or(false);
+
+        paths.push(path.clone());
+
+        if is_real_dir {
+            walk(&format!("{}/", path), paths);
+        }
+    }
+}
+
+/* Expands a pattern into the sorted list of paths it matches, which is empty
+ * if nothing matched */
+pub fn glob(pattern: &str, globstar: bool) -> Vec<String> {
+    /* Each of these is a directory matched so far, ending in a '/', or empty
+     * for the current directory */
+    let (mut dirs, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components: Vec<&str> = rest.split('/').collect();
+    let mut matched = Vec::new();
+
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        let mut next = Vec::new();
+
+        for dir in &dirs {
+            let mut found = Vec::new();
+
+            if component.is_empty() {
+                /* Either a doubled '/' or a trailing one, which only matches
+                 * directories */
+                if is_last {
+                    matched.push(dir.clone());
+                } else {
+                    next.push(dir.clone());
+                }
+                continue;
+            } else if *component == "**" && globstar {
+                if !is_last {
+                    next.push(dir.clone());
+                }
+                walk(dir, &mut found);
+            } else if !has_glob_chars(component) {
+                let path = format!("{}{}", dir, unescape(component));
+
+                if exists(&path) {
+                    found.push(path);
+                }
+            } else {
+                for name in dir_entries(dir) {
+                    if matches(component, &name) {
+                        found.push(format!("{}{}", dir, name));
+                    }
+                }
+            }
+
+            for path in found {
+                if is_last {
+                    matched.push(path);
+                } else if is_dir(&path) {
+                    next.push(format!("{}/", path));
+                }
+            }
+        }
+
+        dirs = next;
+    }
+
+    matched.sort();
+    matched.dedup();
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.o", "main.o"));
+        assert!(matches("*", "x"));
+        assert!(!matches("*.o", "main.c"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(matches("[abc].o", "b.o"));
+        assert!(!matches("[!abc].o", "b.o"));
+        assert!(matches("[^abc].o", "d.o"));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[x", "[x"));
+    }
+
+    #[test]
+    fn escapes_and_hidden_files() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!(matches(&escape("a[1]*"), "a[1]*"));
+        assert!(!has_glob_chars(&escape("a[1]*?")));
+        assert!(!matches("*", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+    }
+
+    /* Makes a fresh directory with the given files in it, where names ending
+     * in '/' are directories */
+    fn make_tree(name: &str, files: &[&str]) -> String {
+        let dir = format!("{}/rshell-glob-{}-{}", std::env::temp_dir().display(), std::process::id(), name);
+        let _ = fs::remove_dir_all(&dir);
+
+        for file in files {
+            let path = format!("{}/{}", dir, file);
+
+            if file.ends_with('/') {
+                fs::create_dir_all(&path).unwrap();
+            } else {
+                fs::write(&path, "").unwrap();
+            }
+        }
+
+        dir
+    }
+
+    #[test]
+    fn paths_in_directories() {
+        let dir = make_tree("paths", &["src/", "lib/", "src/b.rs", "src/a.rs", "src/.c.rs", "lib/d.rs", "lib/e.txt", "top.rs"]);
+        let paths = |pattern: &str| -> Vec<String> {
+            glob(&format!("{}/{}", dir, pattern), false).iter().map(|p| p[dir.len() + 1..].to_string()).collect()
+        };
+
+        assert_eq!(paths("*"), vec!["lib", "src", "top.rs"]);
+        assert_eq!(paths("*/*.rs"), vec!["lib/d.rs", "src/a.rs", "src/b.rs"]);
+        assert_eq!(paths("src/.*"), vec!["src/.c.rs"]);
+        assert_eq!(paths("*/"), vec!["lib/", "src/"]);
+        assert_eq!(paths("[ls]*/[!a]*"), vec!["lib/d.rs", "lib/e.txt", "src/b.rs"]);
+        assert_eq!(paths("top.rs"), vec!["top.rs"]);
+        assert!(paths("*.c").is_empty());
+        assert!(paths("nothing/*").is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn globstar() {
+        let dir = make_tree("globstar", &["a/b/c/", "a/.h/", "a/x.rs", "a/b/y.rs", "a/b/c/z.rs", "a/.h/w.rs", "v.rs"]);
+        let paths = |pattern: &str, globstar: bool| -> Vec<String> {
+            glob(&format!("{}/{}", dir, pattern), globstar).iter().map(|p| p[dir.len() + 1..].to_string()).collect()
+        };
+
+        assert_eq!(paths("**", true), vec!["a", "a/b", "a/b/c", "a/b/c/z.rs", "a/b/y.rs", "a/x.rs", "v.rs"]);
+        assert_eq!(paths("**/*.rs", true), vec!["a/b/c/z.rs", "a/b/y.rs", "a/x.rs", "v.rs"]);
+        assert_eq!(paths("a/**/*.rs", true), vec!["a/b/c/z.rs", "a/b/y.rs", "a/x.rs"]);
+
+        /* Without globstar it is just a '*' */
+        assert_eq!(paths("**/*.rs", false), vec!["a/x.rs"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
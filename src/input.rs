@@ -0,0 +1,102 @@
+use job_list;
+use libc;
+use exec;
+use std::io;
+use std::io::Write;
+use ::*;
+
+/* Reads the lines the shell runs from stdin. While it waits for a line, the
+ * jobs that change state are taken care of, so that finished children don't
+ * stay around as zombies until the next command. */
+pub struct InputReader {
+    buf: Vec<u8>,
+}
+
+impl InputReader {
+    pub fn new() -> InputReader {
+        InputReader {
+            buf: Vec::new(),
+        }
+    }
+
+    /* Prints the prompt and reads the next line, without its newline. Returns
+     * None at the end of the input. */
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        io::stdout().flush().unwrap();
+
+        loop {
+            if let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..end + 1).collect();
+                return Some(String::from_utf8_lossy(&line[..end]).into_owned());
+            }
+
+            if !self.wait_for_input(prompt) {
+                return None;
+            }
+
+            let mut chunk = [0u8; 4096];
+            let len = unsafe { libc::read(libc::STDIN_FILENO, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) };
+
+            if len > 0 {
+                self.buf.extend_from_slice(&chunk[..len as usize]);
+            } else if len == 0 {
+                /* A last line without a newline */
+                if self.buf.is_empty() {
+                    return None;
+                }
+
+                let line: Vec<u8> = self.buf.drain(..).collect();
+                return Some(String::from_utf8_lossy(&line).into_owned());
+            } else if exec::last_errno() != libc::EINTR {
+                return None;
+            }
+        }
+    }
+
+    /* Waits for stdin to become readable. Jobs that finish in the meantime are
+     * reaped, and with 'set -b' reported right away, after which the prompt is
+     * printed again. Returns false if stdin can't be waited on, which is
+     * treated like the end of the input. */
+    fn wait_for_input(&self, prompt: &str) -> bool {
+        let pipe = job_list::child_pipe();
+
+        if pipe == -1 {
+            return true;
+        }
+
+        loop {
+            let mut fds = [
+                libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: pipe, events: libc::POLLIN, revents: 0 },
+            ];
+
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+                if exec::last_errno() == libc::EINTR {
+                    continue;
+                }
+
+                return false;
+            }
+
+            if fds[1].revents != 0 {
+                job_list::drain_child_pipe();
+
+                let mut job_list = JOB_LIST.lock().unwrap();
+                job_list.update_job_list();
+
+                if !job_list.notices.is_empty() && OPTIONS.lock().unwrap().is_set("notify") {
+                    println!();
+                    job_list.print_notices();
+
+                    print!("{}", prompt);
+                    io::stdout().flush().unwrap();
+                }
+            }
+
+            if fds[0].revents != 0 {
+                return true;
+            }
+        }
+    }
+}
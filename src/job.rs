@@ -1,9 +1,47 @@
 
 use prog::*;
 use libc;
-use lexer::*;
-use std::ffi::CString;
-use builtin::*;
+use std::sync::*;
+use std::fmt;
+use std::mem;
+use ::*;
+
+/* The terminal modes set up by a program, such as the raw mode of an editor */
+#[derive(Clone, Copy)]
+pub struct TermModes(libc::termios);
+
+impl TermModes {
+    /* The current modes of the terminal on stdin */
+    pub fn get() -> Option<TermModes> {
+        TermModes::get_fd(libc::STDIN_FILENO)
+    }
+
+    pub fn set(&self) {
+        self.set_fd(libc::STDIN_FILENO);
+    }
+
+    fn get_fd(fd: libc::c_int) -> Option<TermModes> {
+        unsafe {
+            let mut modes: libc::termios = mem::zeroed();
+
+            if libc::tcgetattr(fd, &mut modes) == 0 {
+                Some(TermModes(modes))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn set_fd(&self, fd: libc::c_int) {
+        unsafe { libc::tcsetattr(fd, libc::TCSADRAIN, &self.0); }
+    }
+}
+
+impl fmt::Debug for TermModes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TermModes {{ }}")
+    }
+}
 
 #[derive(PartialEq, Debug)]
 pub enum JobState {
@@ -13,170 +51,224 @@ pub enum JobState {
 
 #[derive(Debug)]
 pub struct Job {
+    /* The job's number, as in '%1', which is given out by the JobList */
+    pub id: usize,
     pub pgrp: libc::pid_t,
     pub name: String,
     pub state: JobState,
     pub progs: Vec<Prog>,
     pub is_background: bool,
+    /* The exit status of the last prog once the job has finished, or 128 plus
+     * the signal that stopped it */
+    pub status: i32,
+    /* The terminal modes the job had when it was stopped, to give back to it
+     * when it is put in the forground again */
+    pub tmodes: Option<TermModes>,
 }
 
 impl Job {
     pub fn new() -> Job {
         Job {
+            id: 0,
             pgrp: -1,
             name: "".to_string(),
             state: JobState::Stopped,
             progs: Vec::new(),
             is_background: false,
+            status: 0,
+            tmodes: None,
         }
     }
 
-    pub fn start(&mut self) {
-        let mut pgrp: libc::c_int = 0;
+    /* Forks every prog in the job. Without job control the progs stay in the
+     * shell's process group instead of getting one of their own. */
+    pub fn start(&mut self, job_control: bool) {
+        let mut pgrp: libc::c_int = if job_control { 0 } else { -1 };
 
         for prog in &mut self.progs {
             prog.pgrp = pgrp;
+            prog.is_background = self.is_background;
             prog.run();
 
-
             if pgrp == 0 {
                 pgrp = prog.pid;
                 prog.pgrp = prog.pid;
             }
         }
 
+        if pgrp == -1 {
+            pgrp = unsafe { libc::getpgrp() };
+        }
+
         self.pgrp = pgrp;
         self.state = JobState::Running;
     }
 
+    /* Sends a signal to every prog in the job, returning false if it couldn't
+     * be sent. A stopped job is continued after SIGTERM or SIGHUP, so that it
+     * gets to act on it. */
+    pub fn signal(&mut self, sig: libc::c_int) -> bool {
+        if !self.send(sig) {
+            return false;
+        }
+
+        if sig == libc::SIGCONT {
+            self.state = JobState::Running;
+        } else if self.state == JobState::Stopped && (sig == libc::SIGTERM || sig == libc::SIGHUP) {
+            self.send(libc::SIGCONT);
+        }
+
+        true
+    }
+
+    /* Without job control the progs share the shell's process group, so they
+     * are signalled one at a time instead */
+    fn send(&self, sig: libc::c_int) -> bool {
+        if self.pgrp != unsafe { libc::getpgrp() } {
+            return unsafe { libc::kill(-self.pgrp, sig) } == 0;
+        }
+
+        let mut sent = false;
+
+        for prog in self.progs.iter().filter(|prog| prog.status.is_none() && prog.pid > 0) {
+            if unsafe { libc::kill(prog.pid, sig) } == 0 {
+                sent = true;
+            }
+        }
+
+        sent
+    }
+
     pub fn kill(&mut self) {
-        unsafe { libc::kill(-self.pgrp, libc::SIGKILL); }
+        self.signal(libc::SIGKILL);
     }
 
     pub fn stop(&mut self) {
-        unsafe { libc::kill(-self.pgrp, libc::SIGSTOP); }
+        self.signal(libc::SIGSTOP);
         self.state = JobState::Stopped;
     }
 
     pub fn cont(&mut self) {
-        unsafe { libc::kill(-self.pgrp, libc::SIGCONT); }
+        self.signal(libc::SIGCONT);
         self.state = JobState::Running;
     }
 
     pub fn has_exited(&mut self) -> bool {
         for prog in &self.progs {
-            if prog.pid != -1 {
+            if prog.status.is_none() {
                 return false;
             }
         }
 
-        return true;
+        true
+    }
+
+    /* The status of a pipeline is the status of its last command */
+    pub fn last_prog_status(&self) -> ExitStatus {
+        match self.progs.last() {
+            Some(prog) => prog.status.unwrap_or(ExitStatus::Exited(0)),
+            None => ExitStatus::Exited(0),
+        }
     }
 
     pub fn add_prog(&mut self, prog: Prog) {
         self.progs.push(prog);
     }
 
-    pub fn parse_job(mut lex: &mut InputLexer) -> Option<Job> {
-        let mut job: Job = Job::new();
-        let mut cur_prog: Prog = Prog::new();
-        let mut opt_tok: Option<InputToken>;
-        let mut job_err: bool = false;
-
-        'lop: while let Some(tok) = lex.next() {
-            match tok {
-                InputToken::Comment => {
-                    while { opt_tok = lex.next();
-                            opt_tok.is_some() && opt_tok.unwrap() != InputToken::NewLine }
-                    { ; }
-                    break;
-                },
-                InputToken::Identifier(s) => {
-                    if cur_prog.file == "" {
-                        cur_prog.file = s;
-                        cur_prog.builtin = builtin_find_callback(&cur_prog.file);
-                    } else {
-                        cur_prog.add_arg(&s);
-                    }
-                },
-                InputToken::NewLine => {
-                    break;
-                },
-                InputToken::RedirectOut | InputToken::RedirectAppendOut | InputToken::RedirectIn => {
-                    opt_tok = lex.next();
-                    match opt_tok {
-                        Some(InputToken::Identifier(s)) => {
-                            if tok == InputToken::RedirectOut {
-                                cur_prog.stdout = unsafe { libc::open(CString::new(s).unwrap().as_ptr(),
-                                                            libc::O_WRONLY | libc::O_CREAT, 0777) };
-                            } else if tok == InputToken::RedirectAppendOut {
-                                cur_prog.stdout = unsafe { libc::open(CString::new(s).unwrap().as_ptr(),
-                                                            libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND, 0777) };
-                            } else if tok == InputToken::RedirectIn {
-                                cur_prog.stdin = unsafe { libc::open(CString::new(s).unwrap().as_ptr(), libc::O_RDONLY) };
-                            }
-                        },
-                        _ => {
-                            println!("Error: Redirect requires filename");
-                            job_err = true;
-                            break 'lop;
-                        }
-                    }
-                },
-                InputToken::Pipe => {
-                    let mut pipefd: [libc::c_int; 2] = [0, 2];
-                    unsafe {
-                        libc::pipe(&mut pipefd[0] as *mut libc::c_int);
-
-                        libc::fcntl(pipefd[0], libc::F_SETFD, libc::fcntl(pipefd[0], libc::F_GETFD) | libc::FD_CLOEXEC);
-                        libc::fcntl(pipefd[1], libc::F_SETFD, libc::fcntl(pipefd[1], libc::F_GETFD) | libc::FD_CLOEXEC);
-                    }
-
-                    cur_prog.stdout = pipefd[1];
-
-                    job.add_prog(cur_prog);
-                    cur_prog = Prog::new();
-
-                    cur_prog.stdin = pipefd[0];
-                },
-                InputToken::Background => {
-                    job.is_background = true;
-                }
-                t => {
-                    println!("Error: Unexpected token {:?}", t);
-                    job_err = true;
-                    break 'lop;
-                }
-            }
+    /* Runs the job to completion if it is in the forground and returns its exit
+     * status. Background jobs are left running and are considered successful. */
+    pub fn run(self) -> i32 {
+        if self.progs.is_empty() {
+            return 0;
         }
 
-        if cur_prog.file != "" {
-            job.add_prog(cur_prog);
-        } else {
-            cur_prog.close_fds();
-        }
+        let is_background = self.is_background;
+        let job = self.launch();
 
-        if !job_err {
-            Some(job)
+        JOB_LIST.lock().unwrap().update_job_list();
+
+        if is_background {
+            0
         } else {
-            None
+            let status = job.lock().unwrap().status;
+            status
         }
     }
 
-    pub fn is_simple_bulitin(&mut self) -> bool {
-        if self.progs.len() != 1 {
-            return false;
+    /* Starts the job and adds it to the job list, without waiting for it. The
+     * job becomes the forground job unless it runs in the background. */
+    pub fn launch(mut self) -> Arc<Mutex<Job>> {
+        /* The job is started before taking the JOB_LIST lock, so that the
+         * children don't inherit it locked */
+        let job_control = JOB_LIST.lock().unwrap().job_control;
+        self.start(job_control);
+
+        let is_background = self.is_background;
+
+        if is_background {
+            *LAST_BACKGROUND_PID.lock().unwrap() = self.progs.last().unwrap().pid;
         }
 
-        if self.progs[0].builtin.is_none() {
-            return false;
+        let mut job_list = JOB_LIST.lock().unwrap();
+        let new_job = job_list.add_job(self);
+
+        if !is_background {
+            job_list.set_forground_job(Some(new_job.clone()));
+        } else {
+            job_list.set_forground_job(None);
         }
 
-        return true;
+        new_job
     }
 
-    pub fn simple_builtin_run(&mut self) -> usize {
-        self.progs[0].run_builtin()
+    pub fn close_fds(&mut self) {
+        for prog in &mut self.progs {
+            prog.close_fds();
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn terminal_modes() {
+        /* A new pseudo-terminal stands in for the shell's own */
+        let (master, slave) = unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master != -1);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+
+            let mut name = [0 as libc::c_char; 64];
+            assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+
+            (master, libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY))
+        };
+        assert!(slave != -1);
+
+        let echo = |fd| TermModes::get_fd(fd).unwrap().0.c_lflag & libc::ECHO != 0;
+
+        /* The modes saved before a program changes them are the ones put back */
+        let saved = TermModes::get_fd(slave).unwrap();
+        let mut raw = TermModes::get_fd(slave).unwrap();
+        raw.0.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.set_fd(slave);
+        assert!(!echo(slave));
+
+        saved.set_fd(slave);
+        assert!(echo(slave));
+
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+
+        /* Something that isn't a terminal has no modes */
+        let file = File::open("/dev/null").unwrap();
+        assert!(TermModes::get_fd(file.as_raw_fd()).is_none());
+    }
+}
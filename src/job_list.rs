@@ -1,11 +1,139 @@
 
 use job::*;
+use prog::*;
 use libc;
+use std::io;
+use std::io::Write;
+use std::fmt;
 use std::sync::*;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::mem;
+use std::ptr;
+use exec;
+use signal;
+
+/* The ends of the pipe the SIGCHLD handler writes to, which wakes up the shell
+ * while it waits for input. The handler can't take a lock, so these are
+ * atomics instead of being kept in the JobList. */
+static CHILD_PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static CHILD_PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn sigchld_handler(_sig: libc::c_int) {
+    let fd = CHILD_PIPE_WRITE.load(Ordering::Relaxed);
+
+    unsafe {
+        let errno = *libc::__errno_location();
+        libc::write(fd, b"c".as_ptr() as *const libc::c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+/* Installs the SIGCHLD handler. Both ends of its pipe are non-blocking, so a
+ * full pipe never holds up the handler. */
+pub fn watch_children() {
+    unsafe {
+        let mut fds: [libc::c_int; 2] = [-1, -1];
+
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) == -1 {
+            return;
+        }
+
+        /* Kept out of the way of the fds redirections use */
+        for fd in fds.iter_mut() {
+            let moved = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, 10);
+            libc::close(*fd);
+            *fd = moved;
+        }
+
+        CHILD_PIPE_READ.store(fds[0], Ordering::Relaxed);
+        CHILD_PIPE_WRITE.store(fds[1], Ordering::Relaxed);
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = sigchld_handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGCHLD, &action, ptr::null_mut());
+    }
+}
+
+/* The end of the pipe that becomes readable when a child changes state, or -1
+ * if children aren't being watched */
+pub fn child_pipe() -> libc::c_int {
+    CHILD_PIPE_READ.load(Ordering::Relaxed)
+}
+
+/* Whether 'fd' is one of the pipe's ends, which commands can't be given */
+pub fn is_child_pipe(fd: libc::c_int) -> bool {
+    fd != -1 && (fd == CHILD_PIPE_READ.load(Ordering::Relaxed) || fd == CHILD_PIPE_WRITE.load(Ordering::Relaxed))
+}
+
+/* Closes the pipe in a child, before its redirections are applied, so that
+ * an fd it names is never the shell's pipe */
+pub fn close_child_pipe() {
+    for end in &[&CHILD_PIPE_READ, &CHILD_PIPE_WRITE] {
+        let fd = end.swap(-1, Ordering::Relaxed);
+
+        if fd != -1 {
+            unsafe { libc::close(fd); }
+        }
+    }
+}
+
+pub fn drain_child_pipe() {
+    let mut buf = [0u8; 64];
+
+    while unsafe { libc::read(child_pipe(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) } > 0 { }
+}
 
 pub struct JobList {
     pub forground_job: Option<Arc<Mutex<Job>>>,
     pub list: Vec<Arc<Mutex<Job>>>,
+    pub job_control: bool,
+    /* Set once the shell has refused to exit because of stopped jobs, so that
+     * exiting again right away goes through */
+    pub stopped_warning: bool,
+    /* The terminal modes of the shell itself, which are put back whenever it
+     * prompts */
+    pub shell_tmodes: Option<TermModes>,
+    /* Reports of background jobs that finished or stopped, which are printed
+     * before the next prompt */
+    pub notices: Vec<String>,
+    /* The ids of the current job, which is '%+', and the previous job, '%-' */
+    pub current: Option<usize>,
+    pub previous: Option<usize>,
+    /* Background jobs that have finished, kept so that 'wait' can still give
+     * their status */
+    pub finished: Vec<Arc<Mutex<Job>>>,
+    /* The process the jobs are children of. A builtin forked for a pipeline
+     * gets a copy of the list, as for 'jobs | cat', but can't wait for them. */
+    owner: libc::pid_t,
+}
+
+#[derive(Debug)]
+pub enum JobSpecError {
+    NoSuchJob(String),
+    /* A '%string' or '%?string' that more than one job matches */
+    Ambiguous(String),
+}
+
+impl fmt::Display for JobSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JobSpecError::NoSuchJob(ref spec) => write!(f, "{}: no such job", spec),
+            JobSpecError::Ambiguous(ref spec) => write!(f, "{}: ambiguous job spec", spec),
+        }
+    }
+}
+
+/* The most finished jobs kept for 'wait' when there's no lower limit on the
+ * number of children */
+const MAX_FINISHED: usize = 32768;
+
+fn finished_limit() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_CHILD_MAX) } {
+        n if n > 0 => (n as usize).min(MAX_FINISHED),
+        _ => MAX_FINISHED,
+    }
 }
 
 fn rc_ptr_eq(this: &Arc<Mutex<Job>>, other: &Arc<Mutex<Job>>) -> bool {
@@ -17,50 +145,210 @@ impl JobList {
         JobList {
             list: Vec::new(),
             forground_job: None,
+            job_control: true,
+            stopped_warning: false,
+            shell_tmodes: None,
+            notices: Vec::new(),
+            current: None,
+            previous: None,
+            finished: Vec::new(),
+            owner: unsafe { libc::getpid() },
+        }
+    }
+
+    /* In a forked copy of the shell, as in 'wait %1 | cat', the jobs are the
+     * shell's children and not the copy's, so the copy is left with none */
+    pub fn forget_inherited(&mut self) {
+        if self.owner != unsafe { libc::getpid() } {
+            *self = JobList::new();
+            self.job_control = false;
         }
     }
 
     pub fn make_job_forground(&mut self) {
-        match self.forground_job {
-            Some(ref job_ref) => {
-                let mut job = job_ref.lock().unwrap();
+        if let Some(ref job_ref) = self.forground_job {
+            let mut job = job_ref.lock().unwrap();
 
-                unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, (*job).pgrp); }
-                job.cont();
-            },
-            None => {
+            if self.job_control {
+                unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, job.pgrp); }
 
+                if let Some(tmodes) = job.tmodes.take() {
+                    tmodes.set();
+                }
             }
+            job.cont();
         }
     }
 
-    pub fn add_job(&mut self, job: Job) -> Arc<Mutex<Job>> {
-        self.list.push(Arc::new(Mutex::new((job))));
-        {
-            let new_job = self.list.last_mut().unwrap();
-            let mut job_locked = new_job.lock().unwrap();
-            job_locked.start();
+    /* Puts the terminal back the way the shell had it, in case the last
+     * forground job left it in some other mode */
+    pub fn restore_shell_modes(&self) {
+        if !self.job_control {
+            return;
+        }
 
-            if !job_locked.is_background {
-                self.forground_job = Some(new_job.clone());
-            }
+        if let Some(ref tmodes) = self.shell_tmodes {
+            tmodes.set();
         }
+    }
 
-        self.list.last_mut().unwrap().clone()
+    pub fn print_notices(&mut self) {
+        for notice in self.notices.drain(..) {
+            println!("{}", notice);
+        }
+
+        io::stdout().flush().unwrap();
+    }
+
+    /* Only an interactive shell reports on its background jobs */
+    fn add_notice(&mut self, notice: String) {
+        if self.job_control {
+            self.notices.push(notice);
+        }
+    }
+
+    /* Adds a job under the smallest id that isn't taken. The list is kept in
+     * order of the ids. */
+    pub fn add_job(&mut self, mut job: Job) -> Arc<Mutex<Job>> {
+        let mut id = 1;
+        while self.find_id(id).is_some() {
+            id += 1;
+        }
+
+        job.id = id;
+
+        /* A finished job whose pid has been given to one of the new progs
+         * can't be waited for by that pid any more */
+        self.finished.retain(|old| {
+            let old = old.lock().unwrap();
+            !old.progs.iter().any(|prog| job.progs.iter().any(|new| new.pid == prog.pid))
+        });
+
+        let is_background = job.is_background;
+        let new_job = Arc::new(Mutex::new(job));
+        let pos = self.list.iter().position(|j| j.lock().unwrap().id > id).unwrap_or(self.list.len());
+
+        self.list.insert(pos, new_job.clone());
+
+        if is_background {
+            self.set_current(id);
+        } else {
+            self.forground_job = Some(new_job.clone());
+        }
+
+        new_job
+    }
+
+    pub fn find_id(&self, id: usize) -> Option<Arc<Mutex<Job>>> {
+        self.list.iter().find(|j| j.lock().unwrap().id == id).cloned()
+    }
+
+    /* Finds the job a job spec refers to: '%n' by its id, '%+' or '%%' for the
+     * current job, '%-' for the previous one, '%string' for the job whose
+     * command starts with string and '%?string' for the one containing it */
+    pub fn find_job(&self, spec: &str) -> Result<Arc<Mutex<Job>>, JobSpecError> {
+        let rest = spec.strip_prefix('%').unwrap_or(spec);
+
+        let id = match rest {
+            "" | "+" | "%" => self.current,
+            "-" => self.previous,
+            _ if rest.starts_with(|c: char| c.is_ascii_digit()) => rest.parse::<usize>().ok(),
+            _ => {
+                let found: Vec<usize> = self.list.iter()
+                    .map(|j| j.lock().unwrap())
+                    .filter(|j| match rest.strip_prefix('?') {
+                        Some(text) => j.name.contains(text),
+                        None => j.name.starts_with(rest),
+                    })
+                    .map(|j| j.id)
+                    .collect();
+
+                if found.len() > 1 {
+                    return Err(JobSpecError::Ambiguous(spec.to_string()));
+                }
+
+                found.first().cloned()
+            },
+        };
+
+        id.and_then(|id| self.find_id(id)).ok_or_else(|| JobSpecError::NoSuchJob(spec.to_string()))
+    }
+
+    /* Makes a job the current job, and the old current job the previous one */
+    pub fn set_current(&mut self, id: usize) {
+        if self.current != Some(id) {
+            self.previous = self.current;
+            self.current = Some(id);
+        }
+    }
+
+    /* The '+' or '-' shown next to the current and previous jobs */
+    pub fn marker(&self, id: usize) -> char {
+        if self.current == Some(id) {
+            '+'
+        } else if self.previous == Some(id) {
+            '-'
+        } else {
+            ' '
+        }
+    }
+
+    /* Picks new current and previous jobs once the old ones are gone, going
+     * by the newest stopped job and then the newest job of all */
+    fn update_current(&mut self) {
+        if self.current.and_then(|id| self.find_id(id)).is_none() {
+            self.current = self.previous.take();
+        }
+        if self.previous.and_then(|id| self.find_id(id)).is_none() {
+            self.previous = None;
+        }
+
+        let mut ids: Vec<(bool, usize)> = self.list.iter()
+            .map(|j| j.lock().unwrap())
+            .map(|j| (j.state == JobState::Stopped, j.id))
+            .collect();
+        ids.sort();
+
+        while self.current.is_none() || self.previous.is_none() {
+            match ids.pop() {
+                Some((_, id)) if Some(id) == self.current => { },
+                Some((_, id)) if self.current.is_none() => self.current = Some(id),
+                Some((_, id)) => self.previous = Some(id),
+                None => break,
+            }
+        }
     }
 
     pub fn set_forground_job(&mut self, job: Option<Arc<Mutex<Job>>>) {
         self.forground_job = job;
     }
 
+    pub fn remove(&mut self, job: &Arc<Mutex<Job>>) {
+        if let Some(index) = self.list.iter().position(|other| rc_ptr_eq(other, job)) {
+            self.remove_job(index);
+        }
+    }
+
     pub fn remove_job(&mut self, job_index: usize) {
         self.list.remove(job_index);
+        self.update_current();
     }
 
     pub fn is_empty(&mut self) -> bool {
         self.list.is_empty()
     }
 
+    pub fn has_stopped_jobs(&self) -> bool {
+        self.list.iter().any(|job| job.lock().unwrap().state == JobState::Stopped)
+    }
+
+    /* Sends SIGHUP to every job as the shell exits */
+    pub fn hangup(&mut self) {
+        for job in &self.list {
+            job.lock().unwrap().signal(libc::SIGHUP);
+        }
+    }
+
     pub fn find_pgrp(&mut self, pgrp: libc::pid_t) -> (Option<Arc<Mutex<Job>>>, usize) {
         for i in 0..self.list.len() {
             if self.list[i].lock().unwrap().pgrp == pgrp {
@@ -76,7 +364,9 @@ impl JobList {
             let j = self.list[i].lock().unwrap();
 
             for k in 0..j.progs.len() {
-                if j.progs[k].pid == pid {
+                /* The pid of a prog that has finished could belong to some
+                 * other process by now */
+                if j.progs[k].pid == pid && j.progs[k].status.is_none() {
                     *prog_index = k;
                     return (Some(self.list[i].clone()), i);
                 }
@@ -104,65 +394,340 @@ impl JobList {
                 break;
             }
 
-            let mut prog_index: usize = 0;
-            let (job, index) = self.find_pid(pid, &mut prog_index);
+            if self.record_status(pid, wstatus) {
+                return ;
+            }
+        }
+
+    }
 
-            match job {
-                Some(ref j) => {
-                    let mut job = j.lock().unwrap();
+    /* Keeps a finished background job for 'wait', dropping the oldest ones
+     * past the number of children a process may have, like bash does */
+    fn keep_finished(&mut self, job: Arc<Mutex<Job>>) {
+        let limit = finished_limit();
 
-                    unsafe {
-                        let w_exited = libc::WIFEXITED(wstatus);
-                        let w_signaled = libc::WIFSIGNALED(wstatus);
-                        let w_stopped = libc::WIFSTOPPED(wstatus);
-                        let w_continued = libc::WIFCONTINUED(wstatus);
+        self.finished.push(job);
 
-                        if w_exited || w_signaled {
-                            job.progs[prog_index].pid = -1;
+        if self.finished.len() > limit {
+            let excess = self.finished.len() - limit;
+            self.finished.drain(..excess);
+        }
+    }
 
-                            if job.has_exited() {
-                                self.list.remove(index);
-                                if self.forground_job.is_some() && rc_ptr_eq(&j, &self.forground_job.as_mut().unwrap()) {
+    /* The finished job that had a prog with this pid */
+    pub fn find_finished_pid(&self, pid: libc::pid_t) -> Option<Arc<Mutex<Job>>> {
+        self.finished.iter()
+            .find(|job| job.lock().unwrap().progs.iter().any(|prog| prog.pid == pid))
+            .cloned()
+    }
+
+    /* Records the new state of a child, returning true once the forground job
+     * has finished or stopped */
+    fn record_status(&mut self, pid: libc::pid_t, wstatus: libc::c_int) -> bool {
+        let mut prog_index: usize = 0;
+        let (job, index) = self.find_pid(pid, &mut prog_index);
+
+        match job {
+            Some(ref j) => {
+                let mut job = j.lock().unwrap();
+                let is_forground = self.forground_job.is_some() && rc_ptr_eq(j, self.forground_job.as_ref().unwrap());
+
+                unsafe {
+                    let w_exited = libc::WIFEXITED(wstatus);
+                    let w_signaled = libc::WIFSIGNALED(wstatus);
+                    let w_stopped = libc::WIFSTOPPED(wstatus);
+                    let w_continued = libc::WIFCONTINUED(wstatus);
+
+                    if w_exited || w_signaled {
+                        job.progs[prog_index].status = Some(ExitStatus::from_wait(wstatus));
+
+                        if job.has_exited() {
+                            let last_status = job.last_prog_status();
+                            job.status = last_status.code();
+
+                            self.remove_job(index);
+                            if is_forground {
+                                if self.job_control {
                                     libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
-                                    return ;
-                                } else {
-                                    if w_exited {
-                                        println!("[{}] Finished: {}", index + 1, libc::WEXITSTATUS(wstatus));
-                                    } else {
-                                        println!("[{}] Killed by signal {}", index + 1, libc::WTERMSIG(wstatus));
-                                    }
                                 }
+                                self.forground_job = None;
+                                return true;
+                            } else {
+                                let notice = format!("[{}] {}: {}", job.id, last_status, job.name);
+                                self.add_notice(notice);
+                                self.keep_finished(j.clone());
                             }
-                        } else if w_stopped {
-                            if job.state == JobState::Stopped {
-                                continue;
-                            }
+                        }
+                    } else if w_stopped {
+                        if job.state == JobState::Stopped {
+                            return false;
+                        }
 
-                            job.stop();
+                        job.stop();
 
-                            if self.forground_job.is_some() {
-                                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                        if self.forground_job.is_some() && self.job_control {
+                            if is_forground {
+                                job.tmodes = TermModes::get();
                             }
 
-                            println!("[{}] Stopped", index + 1);
+                            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpid());
+                        }
 
-                            if rc_ptr_eq(&j, &self.forground_job.as_mut().unwrap()) {
-                                self.forground_job = None;
-                                return ;
-                            }
+                        job.status = 128 + libc::WSTOPSIG(wstatus);
+                        self.set_current(job.id);
 
-                            self.make_job_forground();
-                        } else if w_continued {
-                            job.start();
+                        if is_forground {
+                            println!("[{}]{} Stopped: {}", job.id, self.marker(job.id), job.name);
+                            self.forground_job = None;
+                            return true;
                         }
+
+                        let notice = format!("[{}]{} Stopped: {}", job.id, self.marker(job.id), job.name);
+                        self.add_notice(notice);
+
+                        self.make_job_forground();
+                    } else if w_continued {
+                        job.state = JobState::Running;
                     }
-                },
-                None => {
-                    panic!("Unknown Pid");
                 }
+            },
+            None => {
+                /* A child of a job that was disowned */
+            }
+        }
+
+        false
+    }
+}
+
+/* Waits for a job in 'list' to finish or stop without putting it in the
+ * forground, and returns its status. Once a finished job is waited for, its status isn't
+ * kept any longer. The job list is only locked in between the calls to
+ * waitpid(), and not while the shell is blocked in them. */
+pub fn wait_for_job(list: &Mutex<JobList>, job: &Arc<Mutex<Job>>) -> i32 {
+    loop {
+        {
+            let mut job_list = list.lock().unwrap();
+            let j = job.lock().unwrap();
+
+            if j.state == JobState::Stopped {
+                return j.status;
+            }
+
+            if !job_list.list.iter().any(|other| rc_ptr_eq(other, job)) {
+                job_list.finished.retain(|other| !rc_ptr_eq(other, job));
+                return j.status;
+            }
+        }
+
+        let pgrp = job.lock().unwrap().pgrp;
+        let pid: libc::pid_t;
+        let mut wstatus: libc::c_int = 0;
+
+        unsafe { pid = libc::waitpid(-pgrp, &mut wstatus as *mut libc::c_int, libc::WUNTRACED); }
+
+        if pid == -1 {
+            if exec::last_errno() == libc::EINTR {
+                if signal::interrupted() {
+                    return 128 + libc::SIGINT;
+                }
+                continue;
+            }
+
+            return job.lock().unwrap().status;
+        }
+
+        list.lock().unwrap().record_status(pid, wstatus);
+    }
+}
+
+/* Waits for the next of 'jobs' to finish, or of any job if 'any' is set, and
+ * returns its status. A job that has already finished counts as the next one.
+ * Returns None if there is nothing left to wait for. Like wait_for_job(), the
+ * job list isn't locked while waiting. */
+pub fn wait_for_any(list: &Mutex<JobList>, any: bool, jobs: &[Arc<Mutex<Job>>]) -> Option<i32> {
+    let wanted = |job: &Arc<Mutex<Job>>| any || jobs.iter().any(|other| rc_ptr_eq(other, job));
+
+    loop {
+        {
+            let mut job_list = list.lock().unwrap();
+
+            if let Some(pos) = job_list.finished.iter().position(&wanted) {
+                let job = job_list.finished.remove(pos);
+                let status = job.lock().unwrap().status;
+                return Some(status);
+            }
+
+            let running = job_list.list.iter()
+                .filter(|job| wanted(job))
+                .any(|job| job.lock().unwrap().state == JobState::Running);
+
+            if !running {
+                return None;
+            }
+        }
+
+        let pid: libc::pid_t;
+        let mut wstatus: libc::c_int = 0;
+
+        unsafe { pid = libc::waitpid(-1, &mut wstatus as *mut libc::c_int, libc::WUNTRACED); }
+
+        if pid == -1 {
+            if exec::last_errno() == libc::EINTR {
+                if signal::interrupted() {
+                    return Some(128 + libc::SIGINT);
+                }
+                continue;
             }
+
+            return None;
         }
 
+        list.lock().unwrap().record_status(pid, wstatus);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_with_pid(pid: libc::pid_t) -> Job {
+        let mut job = Job::new();
+        let mut prog = Prog::new();
+
+        prog.pid = pid;
+        prog.status = Some(ExitStatus::Exited(pid));
+        job.add_prog(prog);
+        job.is_background = true;
+        job
+    }
+
+    #[test]
+    fn finished_jobs() {
+        let mut job_list = JobList::new();
+        let limit = finished_limit();
+
+        for pid in 100..limit as libc::pid_t + 101 {
+            job_list.keep_finished(Arc::new(Mutex::new(job_with_pid(pid))));
+        }
+
+        /* The oldest one is dropped past the limit */
+        assert_eq!(job_list.finished.len(), limit);
+        assert!(job_list.find_finished_pid(100).is_none());
+        assert!(job_list.find_finished_pid(101).is_some());
+
+        /* As is one whose pid is used again */
+        job_list.add_job(job_with_pid(101));
+        assert!(job_list.find_finished_pid(101).is_none());
+        assert!(job_list.find_finished_pid(102).is_some());
+    }
+
+    #[test]
+    fn recorded_statuses() {
+        let mut job_list = JobList::new();
+        let mut job = Job::new();
+
+        job.name = "a | b".to_string();
+        job.is_background = true;
+        job.state = JobState::Running;
+
+        for pid in &[100, 101] {
+            let mut prog = Prog::new();
+            prog.pid = *pid;
+            job.add_prog(prog);
+        }
+
+        let job = job_list.add_job(job);
+
+        /* Each prog gets its own status, and the job goes on until the last
+         * one has finished */
+        assert!(!job_list.record_status(100, 3 << 8));
+        assert_eq!(job.lock().unwrap().progs[0].status, Some(ExitStatus::Exited(3)));
+        assert!(job_list.find_job("%1").is_ok());
+
+        /* The job then has the status of its last prog, which being killed
+         * makes 128 plus the signal */
+        assert!(!job_list.record_status(101, libc::SIGKILL));
+        assert_eq!(job.lock().unwrap().progs[1].status, Some(ExitStatus::Signaled(libc::SIGKILL)));
+        assert_eq!(job.lock().unwrap().status, 128 + libc::SIGKILL);
+        assert!(job_list.list.is_empty());
+        assert_eq!(job_list.notices, vec!["[1] Killed (SIGKILL): a | b"]);
+        assert!(job_list.find_finished_pid(101).is_some());
+
+        /* A pid that isn't in any job is ignored */
+        assert!(!job_list.record_status(102, 0));
+    }
+
+    #[test]
+    fn stopped_jobs() {
+        let mut job_list = JobList::new();
+        let mut running = Job::new();
+        running.state = JobState::Running;
+        job_list.add_job(running);
+
+        assert!(!job_list.has_stopped_jobs());
+
+        let stopped = job_list.add_job(Job::new());
+        assert!(job_list.has_stopped_jobs());
+
+        /* Once disowned, it no longer holds up an exit */
+        job_list.remove(&stopped);
+        assert!(!job_list.has_stopped_jobs());
+    }
+
+    #[test]
+    fn inherited_jobs() {
+        let mut job_list = JobList::new();
+        job_list.add_job(job_with_pid(100));
+
+        /* The jobs stay with the process that started them */
+        job_list.forget_inherited();
+        assert!(job_list.find_job("%1").is_ok());
+        assert!(job_list.job_control);
+
+        /* But not with a forked copy of it */
+        job_list.owner += 1;
+        job_list.forget_inherited();
+        assert!(job_list.list.is_empty());
+        assert!(job_list.find_job("%1").is_err());
+        assert!(!job_list.job_control);
+    }
+
+    fn job_id(job_list: &JobList, spec: &str) -> Result<usize, String> {
+        job_list.find_job(spec).map(|job| job.lock().unwrap().id).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn job_specs() {
+        let mut job_list = JobList::new();
+
+        for name in &["sleep 10", "sleep 20 | cat", "vim notes"] {
+            let mut job = Job::new();
+            job.name = name.to_string();
+            job.is_background = true;
+            job_list.add_job(job);
+        }
+
+        for spec in &["%+", "%%", "%", "+", "%3", "3", "%vim", "%?notes"] {
+            assert_eq!(job_id(&job_list, spec), Ok(3), "{}", spec);
+        }
+        assert_eq!(job_id(&job_list, "%-"), Ok(2));
+        assert_eq!(job_id(&job_list, "%1"), Ok(1));
+        assert_eq!(job_id(&job_list, "%?20"), Ok(2));
+        assert_eq!(job_id(&job_list, "%sleep"), Err("%sleep: ambiguous job spec".to_string()));
+        assert_eq!(job_id(&job_list, "%?zzz"), Err("%?zzz: no such job".to_string()));
+        assert_eq!(job_id(&job_list, "%9"), Err("%9: no such job".to_string()));
+
+        /* The previous job takes over once the current one is gone, and the
+         * freed id is given out again */
+        let current = job_list.find_job("%+").unwrap();
+        job_list.remove(&current);
+
+        assert_eq!(job_id(&job_list, "%+"), Ok(2));
+        assert_eq!(job_id(&job_list, "%-"), Ok(1));
+        assert_eq!(job_list.marker(2), '+');
+
+        let id = job_list.add_job(Job::new()).lock().unwrap().id;
+        assert_eq!(id, 3);
+    }
+}
@@ -1,41 +1,514 @@
 
 use std::str::*;
 use std::iter::*;
+use std::fmt;
+use ast::*;
 
 #[derive(Debug, PartialEq)]
 pub enum InputToken {
-    Identifier(String),
+    Identifier(Word),
+    /* A file descriptor number right before a redirection operator, as in
+     * '2>' */
+    IoNumber(i32),
     RedirectIn,
     RedirectOut,
     RedirectAppendOut,
+    RedirectClobber,
+    RedirectReadWrite,
+    RedirectDupIn,
+    RedirectDupOut,
+    /* '&>' and '&>>', which redirect both stdout and stderr */
+    RedirectAllOut,
+    RedirectAllAppendOut,
+    /* A '<<' or '<<-' here-document, with its delimiter and the body read
+     * from the lines after the command */
+    HereDoc(String, Word),
+    /* A '<<' that isn't followed by a delimiter */
+    HereDocOp,
+    HereString,
     Pipe,
     Background,
+    Semicolon,
     LogicAnd,
     LogicOr,
+    LeftParen,
+    RightParen,
     Comment,
     NewLine,
+    /* The input ended in the middle of a quote or after a '\' */
+    Unterminated,
+}
+
+impl fmt::Display for InputToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputToken::Identifier(ref s) => write!(f, "{}", s),
+            InputToken::IoNumber(fd) => write!(f, "{}", fd),
+            InputToken::RedirectIn => write!(f, "<"),
+            InputToken::RedirectOut => write!(f, ">"),
+            InputToken::RedirectAppendOut => write!(f, ">>"),
+            InputToken::RedirectClobber => write!(f, ">|"),
+            InputToken::RedirectReadWrite => write!(f, "<>"),
+            InputToken::RedirectDupIn => write!(f, "<&"),
+            InputToken::RedirectDupOut => write!(f, ">&"),
+            InputToken::RedirectAllOut => write!(f, "&>"),
+            InputToken::RedirectAllAppendOut => write!(f, "&>>"),
+            InputToken::HereDoc(_, _) | InputToken::HereDocOp => write!(f, "<<"),
+            InputToken::HereString => write!(f, "<<<"),
+            InputToken::Pipe => write!(f, "|"),
+            InputToken::Background => write!(f, "&"),
+            InputToken::Semicolon => write!(f, ";"),
+            InputToken::LogicAnd => write!(f, "&&"),
+            InputToken::LogicOr => write!(f, "||"),
+            InputToken::LeftParen => write!(f, "("),
+            InputToken::RightParen => write!(f, ")"),
+            InputToken::Comment => write!(f, "#"),
+            InputToken::NewLine => write!(f, "newline"),
+            InputToken::Unterminated => write!(f, "end of file"),
+        }
+    }
+}
+
+/* Parameters whose name is a single special character, such as '$?' */
+pub const SPECIAL_PARAMS: &str = "$!?#@*0123456789";
+
+/* Appends a part to a word, merging neighbouring literal text together */
+fn push_part(parts: &mut Vec<WordPart>, part: WordPart) {
+    if let WordPart::Literal(ref s) = part {
+        if let Some(&mut WordPart::Literal(ref mut last)) = parts.last_mut() {
+            last.push_str(s);
+            return;
+        }
+    }
+
+    parts.push(part);
+}
+
+/* The expression of a '$((...))' arithmetic expansion, given the source read
+ * for it as a command substitution. That's when the whole source is inside of
+ * one pair of parentheses, as '$((a) && (b))' is a command substitution of two
+ * subshells instead. */
+fn arith_source(source: &str) -> Option<&str> {
+    if !source.starts_with('(') || !source.ends_with(')') {
+        return None;
+    }
+
+    let mut depth = 0;
+
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return if i == source.len() - 1 { Some(&source[1..i]) } else { None };
+                }
+            },
+            _ => { },
+        }
+    }
+
+    None
+}
+
+/* The text of a here-document delimiter after quote removal, and whether any
+ * of it was quoted */
+fn heredoc_delimiter(word: &Word) -> (String, bool) {
+    let mut delim = String::new();
+    let mut quoted = false;
+
+    for part in &word.parts {
+        match *part {
+            WordPart::Literal(ref s) => delim += s,
+            WordPart::SingleQuoted(ref s) => {
+                delim += s;
+                quoted = true;
+            },
+            WordPart::Escaped(c) => {
+                delim.push(c);
+                quoted = true;
+            },
+            WordPart::DoubleQuoted(ref inner) => {
+                delim += &Word { parts: inner.clone() }.to_string();
+                quoted = true;
+            },
+            _ => delim += &Word { parts: vec![part.clone()] }.to_string(),
+        }
+    }
+
+    (delim, quoted)
 }
 
 pub struct InputLexer<'a> {
     input: Peekable<Chars<'a>>,
+    /* Set when the input ends inside of a quote */
+    unterminated: bool,
+    /* A token that was read ahead and is given out next */
+    pending: Option<InputToken>,
+    /* Where the input continues after the bodies of the here-documents on the
+     * current line, which are skipped at the end of the line */
+    heredoc_end: Option<Peekable<Chars<'a>>>,
 }
 
 impl<'a> InputLexer<'a> {
-    pub fn new(inp: &'a str) -> InputLexer {
-        InputLexer { input: inp.chars().peekable() }
+    pub fn new(inp: &'a str) -> InputLexer<'a> {
+        InputLexer {
+            input: inp.chars().peekable(),
+            unterminated: false,
+            pending: None,
+            heredoc_end: None,
+        }
     }
 
     fn peek_char(&mut self) -> char {
         *self.input.peek().unwrap_or(&'\0')
     }
 
+    /* Characters that end a word when they aren't quoted */
+    fn is_meta_char(c: char) -> bool {
+        c == ' '
+            || c == '\t'
+            || c == '\n'
+            || c == '\0'
+            || c == '|'
+            || c == '&'
+            || c == ';'
+            || c == '<'
+            || c == '>'
+            || c == '('
+            || c == ')'
+    }
+
+    /* Reads the parameter following a '$', either '${NAME}', '$NAME', or one
+     * of the special parameters, or a '$(...)' command substitution. A '$'
+     * that doesn't start one of those is taken literally. */
+    fn read_param(&mut self) -> WordPart {
+        let c = self.peek_char();
+
+        if c == '(' {
+            self.input.next();
+
+            let part = self.read_command_subst();
+            if let WordPart::CommandSubst(ref source) = part {
+                if let Some(expr) = arith_source(source) {
+                    return WordPart::Arith(InputLexer::new(expr).read_arith_parts());
+                }
+            }
+
+            return part;
+        }
+
+        /* Whatever is inside of the braces is kept as the name, and checked
+         * when it is expanded */
+        if c == '{' {
+            self.input.next();
+
+            let mut name = String::new();
+
+            loop {
+                match self.input.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => {
+                        self.unterminated = true;
+                        break;
+                    },
+                }
+            }
+
+            return WordPart::Param(name);
+        }
+
+        if SPECIAL_PARAMS.contains(c) {
+            self.input.next();
+            return WordPart::Param(c.to_string());
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+
+            while self.peek_char().is_ascii_alphanumeric() || self.peek_char() == '_' {
+                name.push(self.input.next().unwrap());
+            }
+
+            return WordPart::Param(name);
+        }
+
+        WordPart::Literal("$".to_string())
+    }
+
+    /* Reads the source of a command substitution up to the matching ')'.
+     * Parentheses inside of quotes don't count towards the nesting. */
+    fn read_command_subst(&mut self) -> WordPart {
+        let mut source = String::new();
+        let mut depth = 1;
+
+        while let Some(c) = self.input.next() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return WordPart::CommandSubst(source);
+                    }
+                },
+                '\\' => {
+                    source.push(c);
+                    match self.input.next() {
+                        Some(c) => source.push(c),
+                        None => break,
+                    }
+                    continue;
+                },
+                '\'' | '\"' => {
+                    source.push(c);
+
+                    let quote = c;
+                    while let Some(c) = self.input.next() {
+                        source.push(c);
+
+                        if c == quote {
+                            break;
+                        } else if c == '\\' && quote == '\"' {
+                            if let Some(c) = self.input.next() {
+                                source.push(c);
+                            }
+                        }
+                    }
+                    continue;
+                },
+                _ => { },
+            }
+
+            source.push(c);
+        }
+
+        self.unterminated = true;
+        WordPart::CommandSubst(source)
+    }
+
+    /* Reads an old style '`...`' command substitution, after the opening
+     * backquote. A backslash only escapes '$', '`' and itself. */
+    fn read_backquoted(&mut self) -> Option<WordPart> {
+        let mut source = String::new();
+
+        loop {
+            match self.input.next() {
+                Some('`') => return Some(WordPart::CommandSubst(source)),
+                Some('\\') => {
+                    match self.input.next() {
+                        Some(c) if c == '$' || c == '`' || c == '\\' => source.push(c),
+                        Some(c) => {
+                            source.push('\\');
+                            source.push(c);
+                        },
+                        None => {
+                            self.unterminated = true;
+                            return None;
+                        },
+                    }
+                },
+                Some(c) => source.push(c),
+                None => {
+                    self.unterminated = true;
+                    return None;
+                },
+            }
+        }
+    }
+
+    /* Reads the parameters and command substitutions in the expression of an
+     * arithmetic expansion. Everything else is left as literal text. */
+    fn read_arith_parts(&mut self) -> Vec<WordPart> {
+        let mut parts = Vec::new();
+
+        while let Some(c) = self.input.next() {
+            let part = match c {
+                '$' => self.read_param(),
+                '`' => match self.read_backquoted() {
+                    Some(part) => part,
+                    None => break,
+                },
+                _ => WordPart::Literal(c.to_string()),
+            };
+
+            push_part(&mut parts, part);
+        }
+
+        parts
+    }
+
+    /* Reads a here-document after the '<<' or '<<-', returning its delimiter
+     * and body. The body starts on the line after the current one, or after
+     * the last here-document body on this line. */
+    fn read_heredoc(&mut self, strip_tabs: bool) -> InputToken {
+        let word = match self.next() {
+            Some(InputToken::Identifier(word)) => word,
+            tok => {
+                self.pending = tok;
+                return InputToken::HereDocOp;
+            },
+        };
+
+        let (delim, quoted) = heredoc_delimiter(&word);
+
+        let mut input = match self.heredoc_end.take() {
+            Some(input) => input,
+            None => {
+                let mut input = self.input.clone();
+
+                loop {
+                    match input.next() {
+                        Some('\n') => break,
+                        Some(_) => { },
+                        None => {
+                            self.unterminated = true;
+                            return InputToken::Unterminated;
+                        },
+                    }
+                }
+
+                input
+            },
+        };
+
+        let mut body = String::new();
+
+        loop {
+            if input.peek().is_none() {
+                self.unterminated = true;
+                return InputToken::Unterminated;
+            }
+
+            let mut line: String = input.by_ref().take_while(|c| *c != '\n').collect();
+
+            if strip_tabs {
+                line = line.trim_start_matches('\t').to_string();
+            }
+
+            if line == delim {
+                break;
+            }
+
+            body += &line;
+            body.push('\n');
+        }
+
+        self.heredoc_end = Some(input);
+
+        /* The body is expanded like it was in double quotes, unless any of
+         * the delimiter was quoted */
+        let parts = if quoted {
+            vec![WordPart::SingleQuoted(body)]
+        } else {
+            InputLexer::new(&body).read_heredoc_parts()
+        };
+
+        InputToken::HereDoc(delim, Word { parts })
+    }
+
+    fn read_heredoc_parts(&mut self) -> Vec<WordPart> {
+        let mut parts = Vec::new();
+
+        while let Some(c) = self.input.next() {
+            let part = match c {
+                '$' => self.read_param(),
+                '`' => match self.read_backquoted() {
+                    Some(part) => part,
+                    None => break,
+                },
+                '\\' => match self.input.next() {
+                    Some('\n') => continue,
+                    Some(c) if c == '$' || c == '`' || c == '\\' => WordPart::Escaped(c),
+                    Some(c) => WordPart::Literal(format!("\\{}", c)),
+                    None => WordPart::Literal("\\".to_string()),
+                },
+                _ => WordPart::Literal(c.to_string()),
+            };
+
+            push_part(&mut parts, part);
+        }
+
+        parts
+    }
+
+    /* Reads up to the closing quote, after the opening one */
+    fn read_single_quoted(&mut self) -> Option<WordPart> {
+        let mut s = String::new();
+
+        loop {
+            match self.input.next() {
+                Some('\'') => return Some(WordPart::SingleQuoted(s)),
+                Some(c) => s.push(c),
+                None => {
+                    self.unterminated = true;
+                    return None;
+                },
+            }
+        }
+    }
+
+    fn read_double_quoted(&mut self) -> Option<WordPart> {
+        let mut parts = Vec::new();
+
+        loop {
+            match self.input.next() {
+                Some('\"') => return Some(WordPart::DoubleQuoted(parts)),
+                Some('$') => {
+                    let part = self.read_param();
+                    if self.unterminated {
+                        return None;
+                    }
+                    push_part(&mut parts, part);
+                },
+                Some('`') => {
+                    match self.read_backquoted() {
+                        Some(part) => parts.push(part),
+                        None => return None,
+                    }
+                },
+                /* Inside double quotes a backslash only escapes the
+                 * characters that would otherwise be special */
+                Some('\\') => {
+                    match self.input.next() {
+                        Some('\n') => { },
+                        Some(c) if c == '$' || c == '`' || c == '\"' || c == '\\' => parts.push(WordPart::Escaped(c)),
+                        Some(c) => push_part(&mut parts, WordPart::Literal(format!("\\{}", c))),
+                        None => {
+                            self.unterminated = true;
+                            return None;
+                        },
+                    }
+                },
+                Some(c) => push_part(&mut parts, WordPart::Literal(c.to_string())),
+                None => {
+                    self.unterminated = true;
+                    return None;
+                },
+            }
+        }
+    }
+
+    /* Reads the character after a '\'. A backslash before a newline joins the
+     * two lines together, so it results in nothing at all. */
+    fn read_escaped(&mut self) -> Option<WordPart> {
+        match self.input.next() {
+            Some('\n') => None,
+            Some(c) => Some(WordPart::Escaped(c)),
+            None => {
+                self.unterminated = true;
+                None
+            },
+        }
+    }
+
     fn handle_double(&mut self, ch: char, single: Option<InputToken>, double: Option<InputToken>) -> Option<InputToken> {
         if self.peek_char() == ch {
             self.input.next();
             return double;
         }
 
-        return single;
+        single
     }
 }
 
@@ -43,44 +516,286 @@ impl<'a> Iterator for InputLexer<'a> {
     type Item = InputToken;
 
     fn next(&mut self) -> Option<InputToken> {
+        if let Some(tok) = self.pending.take() {
+            return Some(tok);
+        }
+
+        /* Nothing after an unterminated quote or here-document is read */
+        if self.unterminated {
+            return None;
+        }
+
         loop {
             match self.peek_char() {
                 ' ' | '\t' => { self.input.next(); },
                 '\0' => return None,
-                '#' => { self.input.next(); return Some(InputToken::Comment); },
-                '<' => { self.input.next(); return Some(InputToken::RedirectIn); },
+                '#' => {
+                    /* The text of a comment is skipped here, so that quotes
+                     * in it don't start a word */
+                    while self.peek_char() != '\n' && self.peek_char() != '\0' {
+                        self.input.next();
+                    }
+                    return Some(InputToken::Comment);
+                },
+                '<' => {
+                    self.input.next();
+
+                    return match self.peek_char() {
+                        '&' => { self.input.next(); Some(InputToken::RedirectDupIn) },
+                        '>' => { self.input.next(); Some(InputToken::RedirectReadWrite) },
+                        '<' => {
+                            self.input.next();
+
+                            match self.peek_char() {
+                                '<' => { self.input.next(); Some(InputToken::HereString) },
+                                '-' => { self.input.next(); Some(self.read_heredoc(true)) },
+                                _ => Some(self.read_heredoc(false)),
+                            }
+                        },
+                        _ => Some(InputToken::RedirectIn),
+                    };
+                },
                 '>' => {
                     self.input.next();
-                    return self.handle_double('>', Some(InputToken::RedirectOut), Some(InputToken::RedirectAppendOut));
+
+                    return match self.peek_char() {
+                        '>' => { self.input.next(); Some(InputToken::RedirectAppendOut) },
+                        '&' => { self.input.next(); Some(InputToken::RedirectDupOut) },
+                        '|' => { self.input.next(); Some(InputToken::RedirectClobber) },
+                        _ => Some(InputToken::RedirectOut),
+                    };
                 },
                 '&' => {
                     self.input.next();
+
+                    if self.peek_char() == '>' {
+                        self.input.next();
+                        return self.handle_double('>', Some(InputToken::RedirectAllOut), Some(InputToken::RedirectAllAppendOut));
+                    }
+
                     return self.handle_double('&', Some(InputToken::Background), Some(InputToken::LogicAnd));
                 },
                 '|' => {
                     self.input.next();
                     return self.handle_double('|', Some(InputToken::Pipe), Some(InputToken::LogicOr));
                 },
+                ';' => { self.input.next(); return Some(InputToken::Semicolon); },
+                '(' => { self.input.next(); return Some(InputToken::LeftParen); },
+                ')' => { self.input.next(); return Some(InputToken::RightParen); },
                 '\n' => {
                     self.input.next();
+
+                    if let Some(input) = self.heredoc_end.take() {
+                        self.input = input;
+                    }
+
                     return Some(InputToken::NewLine);
                 },
-                '\"' => {
-                    self.input.next();
-                    return Some(InputToken::Identifier(self.input.by_ref().take_while(|c| *c != '\"').collect()));
-                },
                 _ => {
-                    return Some(InputToken::Identifier(self.input.by_ref()
-                                                       .take_while(|c| (*c).is_alphabetic()
-                                                                    || (*c).is_digit(10)
-                                                                    || *c == '_'
-                                                                    || *c == '-'
-                                                                    || *c == '/'
-                                                                    || *c == '.'
-                                                                    ).collect()));
+                    let mut parts = Vec::new();
+
+                    loop {
+                        let c = self.peek_char();
+
+                        if c == '$' {
+                            self.input.next();
+                            let part = self.read_param();
+                            if self.unterminated {
+                                return Some(InputToken::Unterminated);
+                            }
+                            push_part(&mut parts, part);
+                        } else if c == '\'' || c == '\"' || c == '`' || c == '\\' {
+                            self.input.next();
+
+                            let part = match c {
+                                '\'' => self.read_single_quoted(),
+                                '\"' => self.read_double_quoted(),
+                                '`' => self.read_backquoted(),
+                                _ => self.read_escaped(),
+                            };
+
+                            if self.unterminated {
+                                return Some(InputToken::Unterminated);
+                            }
+
+                            if let Some(part) = part {
+                                push_part(&mut parts, part);
+                            }
+                        } else if !InputLexer::is_meta_char(c) {
+                            self.input.next();
+                            push_part(&mut parts, WordPart::Literal(c.to_string()));
+                        } else {
+                            break;
+                        }
+                    }
+
+                    /* A line continuation on its own isn't a word, so the
+                     * next token is looked for again */
+                    if parts.is_empty() {
+                        continue;
+                    }
+
+                    /* Unquoted digits right before a redirection are the fd
+                     * it applies to */
+                    let c = self.peek_char();
+                    if c == '<' || c == '>' {
+                        if let [WordPart::Literal(ref digits)] = parts.as_slice() {
+                            if digits.chars().all(|c| c.is_ascii_digit()) {
+                                if let Ok(fd) = digits.parse::<i32>() {
+                                    return Some(InputToken::IoNumber(fd));
+                                }
+                            }
+                        }
+                    }
+
+                    return Some(InputToken::Identifier(Word { parts }));
                 }
             }
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::InputToken::*;
+
+    fn lex(s: &str) -> Vec<InputToken> {
+        InputLexer::new(s).collect()
+    }
+
+    fn lit(s: &str) -> InputToken {
+        Identifier(Word { parts: vec![WordPart::Literal(s.to_string())] })
+    }
+
+    fn words(s: &str) -> Vec<InputToken> {
+        s.split(' ').map(lit).collect()
+    }
+
+    #[test]
+    fn punctuation_in_words() {
+        assert_eq!(lex("git log --format=%H"), words("git log --format=%H"));
+        assert_eq!(lex("scp a host:b"), words("scp a host:b"));
+        assert_eq!(lex("echo a,b+c@d~e%f^g"), words("echo a,b+c@d~e%f^g"));
+        assert_eq!(lex("ls ~/src [abc]*.rs ?.o"), words("ls ~/src [abc]*.rs ?.o"));
+        assert_eq!(lex("find . -exec rm {} +"), words("find . -exec rm {} +"));
+        assert_eq!(lex("git checkout HEAD~1 -- a.rs"), words("git checkout HEAD~1 -- a.rs"));
+        assert_eq!(lex("ssh user@host:2222"), words("ssh user@host:2222"));
+        assert_eq!(lex("du -sh ./* 2>/dev/null"),
+                   vec![lit("du"), lit("-sh"), lit("./*"), IoNumber(2), RedirectOut, lit("/dev/null")]);
+    }
+
+    #[test]
+    fn metacharacters_end_words() {
+        assert_eq!(lex("ls|wc -l"), vec![lit("ls"), Pipe, lit("wc"), lit("-l")]);
+        assert_eq!(lex("echo a>b"), vec![lit("echo"), lit("a"), RedirectOut, lit("b")]);
+        assert_eq!(lex("cat<in>>out"), vec![lit("cat"), RedirectIn, lit("in"), RedirectAppendOut, lit("out")]);
+        assert_eq!(lex("cd src;make&&ls||true&"),
+                   vec![lit("cd"), lit("src"), Semicolon, lit("make"), LogicAnd,
+                        lit("ls"), LogicOr, lit("true"), Background]);
+        assert_eq!(lex("(cd x)\n"), vec![LeftParen, lit("cd"), lit("x"), RightParen, NewLine]);
+        assert_eq!(lex("curl http://x/?a=1&b=2"),
+                   vec![lit("curl"), lit("http://x/?a=1"), Background, lit("b=2")]);
+    }
+
+    #[test]
+    fn redirections() {
+        assert_eq!(lex("make 2>&1 >|log"),
+                   vec![lit("make"), IoNumber(2), RedirectDupOut, lit("1"), RedirectClobber, lit("log")]);
+        assert_eq!(lex("exec 3<>f 3<&- 4>&-"),
+                   vec![lit("exec"), IoNumber(3), RedirectReadWrite, lit("f"),
+                        IoNumber(3), RedirectDupIn, lit("-"), IoNumber(4), RedirectDupOut, lit("-")]);
+        assert_eq!(lex("cmd &>out &>>log &"),
+                   vec![lit("cmd"), RedirectAllOut, lit("out"), RedirectAllAppendOut, lit("log"), Background]);
+        assert_eq!(lex("echo 2 >x a2>y \"2\">z"),
+                   vec![lit("echo"), lit("2"), RedirectOut, lit("x"), lit("a2"), RedirectOut, lit("y"),
+                        Identifier(Word { parts: vec![WordPart::DoubleQuoted(vec![WordPart::Literal("2".to_string())])] }),
+                        RedirectOut, lit("z")]);
+    }
+
+    #[test]
+    fn here_documents() {
+        let doc = |delim: &str, parts: Vec<WordPart>| HereDoc(delim.to_string(), Word { parts });
+
+        assert_eq!(lex("cat <<EOF\nhi $x\nEOF\necho"),
+                   vec![lit("cat"), doc("EOF", vec![WordPart::Literal("hi ".to_string()),
+                                                     WordPart::Param("x".to_string()),
+                                                     WordPart::Literal("\n".to_string())]),
+                        NewLine, lit("echo")]);
+        assert_eq!(lex("cat <<'EOF'\n$x\nEOF"),
+                   vec![lit("cat"), doc("EOF", vec![WordPart::SingleQuoted("$x\n".to_string())]), NewLine]);
+        assert_eq!(lex("cat <<-EOF\n\t\tx\n\tEOF"),
+                   vec![lit("cat"), doc("EOF", vec![WordPart::Literal("x\n".to_string())]), NewLine]);
+        assert_eq!(lex("a <<A; b <<B\n1\nA\n2\nB"),
+                   vec![lit("a"), doc("A", vec![WordPart::Literal("1\n".to_string())]), Semicolon,
+                        lit("b"), doc("B", vec![WordPart::Literal("2\n".to_string())]), NewLine]);
+        assert_eq!(lex("cat <<EOF\nno end"), vec![lit("cat"), Unterminated]);
+        assert_eq!(lex("cat <<\n"), vec![lit("cat"), HereDocOp, NewLine]);
+        assert_eq!(lex("cat <<<$x"),
+                   vec![lit("cat"), HereString, Identifier(Word { parts: vec![WordPart::Param("x".to_string())] })]);
+    }
+
+    #[test]
+    fn comments_only_start_words() {
+        assert_eq!(lex("echo a#b"), words("echo a#b"));
+        assert_eq!(lex("echo a #b"), vec![lit("echo"), lit("a"), Comment]);
+        assert_eq!(lex("# don't (stop)\necho $#"),
+                   vec![Comment, NewLine, lit("echo"), Identifier(Word { parts: vec![WordPart::Param("#".to_string())] })]);
+    }
+
+    #[test]
+    fn quotes_join_words() {
+        assert_eq!(lex("echo foo\"bar baz\"qux"),
+                   vec![lit("echo"), Identifier(Word { parts: vec![
+                       WordPart::Literal("foo".to_string()),
+                       WordPart::DoubleQuoted(vec![WordPart::Literal("bar baz".to_string())]),
+                       WordPart::Literal("qux".to_string()),
+                   ] })]);
+        assert_eq!(lex("grep -e 'a|b' f"),
+                   vec![lit("grep"), lit("-e"), Identifier(Word { parts: vec![WordPart::SingleQuoted("a|b".to_string())] }), lit("f")]);
+        assert_eq!(lex(r"echo a\;b"),
+                   vec![lit("echo"), Identifier(Word { parts: vec![
+                       WordPart::Literal("a".to_string()),
+                       WordPart::Escaped(';'),
+                       WordPart::Literal("b".to_string()),
+                   ] })]);
+    }
+
+    #[test]
+    fn parameters_in_words() {
+        assert_eq!(lex("cp $HOME/a.txt host:"),
+                   vec![lit("cp"),
+                        Identifier(Word { parts: vec![WordPart::Param("HOME".to_string()), WordPart::Literal("/a.txt".to_string())] }),
+                        lit("host:")]);
+        assert_eq!(lex("echo 5$"), words("echo 5$"));
+        assert_eq!(lex("echo ${FOO:-x}"),
+                   vec![lit("echo"), Identifier(Word { parts: vec![WordPart::Param("FOO:-x".to_string())] })]);
+    }
+
+    #[test]
+    fn command_substitutions() {
+        let subst = |s: &str| Identifier(Word { parts: vec![WordPart::CommandSubst(s.to_string())] });
+
+        assert_eq!(lex("cd $(git rev-parse --show-toplevel)"),
+                   vec![lit("cd"), subst("git rev-parse --show-toplevel")]);
+        assert_eq!(lex("echo $(echo \")\" (a) $(b))"),
+                   vec![lit("echo"), subst("echo \")\" (a) $(b)")]);
+        assert_eq!(lex("echo `date +%s`"), vec![lit("echo"), subst("date +%s")]);
+        assert_eq!(lex(r"echo `echo \`x\``"), vec![lit("echo"), subst("echo `x`")]);
+        assert_eq!(lex("echo \"a $(b)\""),
+                   vec![lit("echo"), Identifier(Word { parts: vec![WordPart::DoubleQuoted(vec![
+                       WordPart::Literal("a ".to_string()),
+                       WordPart::CommandSubst("b".to_string()),
+                   ])] })]);
+        assert_eq!(lex("echo $(ls"), vec![lit("echo"), Unterminated]);
+        assert_eq!(lex("echo `ls"), vec![lit("echo"), Unterminated]);
+    }
+
+    #[test]
+    fn unterminated_input() {
+        assert_eq!(lex("echo 'abc"), vec![lit("echo"), Unterminated]);
+        assert_eq!(lex("echo \\"), vec![lit("echo"), Unterminated]);
+        assert_eq!(lex("echo ${HOME"), vec![lit("echo"), Unterminated]);
+        assert_eq!(lex("echo \"${HOME\""), vec![lit("echo"), Unterminated]);
+    }
+}
@@ -9,70 +9,259 @@ mod job;
 mod builtin;
 mod prog;
 mod job_list;
+mod ast;
+mod parser;
+mod exec;
+mod expand;
+mod var;
+mod glob;
+mod options;
+mod arith;
+mod input;
+mod signal;
 
-use std::io::Write;
-use std::io::BufRead;
-use std::io;
+use std::process;
 use lexer::*;
 use job::*;
 use job_list::*;
+use input::*;
+use parser::*;
+use var::*;
+use options::*;
 use std::sync::*;
 use std::ffi::CString;
 
 lazy_static! {
     pub static ref JOB_LIST: Mutex<JobList> = Mutex::new(JobList::new());
     pub static ref CURRENT_DIR: Mutex<String> = Mutex::new(String::new());
+    /* Exit status of the last forground pipeline, as '$?' */
+    pub static ref LAST_STATUS: Mutex<i32> = Mutex::new(0);
+    pub static ref VARIABLES: Mutex<VarStore> = Mutex::new(VarStore::new());
+    pub static ref OPTIONS: Mutex<ShellOptions> = Mutex::new(ShellOptions::new());
+    /* Values of the special parameters '$$', '$0' and '$!' */
+    pub static ref SHELL_PID: Mutex<libc::pid_t> = Mutex::new(-1);
+    pub static ref SHELL_NAME: Mutex<String> = Mutex::new(String::new());
+    pub static ref LAST_BACKGROUND_PID: Mutex<libc::pid_t> = Mutex::new(-1);
+    /* '$1', '$2', and so on, which are the arguments given to a script */
+    pub static ref POSITIONAL_PARAMS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /* Whether the shell was started as a login shell, with a '-' in front of
+     * its name or the '-l' option */
+    pub static ref LOGIN_SHELL: Mutex<bool> = Mutex::new(false);
 }
 
-fn main() {
-    let mut new_job;
-    let inp = io::stdin();
-    let mut out = io::stdout();
-    let mut lines = inp.lock().lines();
-    let env = std::env::vars();
-
-    match env.filter(|x| x.0 == "HOME").next() {
-        Some((_, cwd)) => {
-            *CURRENT_DIR.lock().unwrap() =  cwd;
-        },
-        _ => { *CURRENT_DIR.lock().unwrap() = "/".to_string() }
+/* Exits the shell, as for 'exit' and the end of input. An interactive shell
+ * with stopped jobs only warns about them the first time, and returns. */
+pub fn exit_shell(status: i32) {
+    let is_shell = unsafe { libc::getpid() } == *SHELL_PID.lock().unwrap();
+    let mut job_list = JOB_LIST.lock().unwrap();
+
+    if is_shell && job_list.job_control {
+        if job_list.has_stopped_jobs() && !job_list.stopped_warning {
+            eprintln!("There are stopped jobs.");
+            job_list.stopped_warning = true;
+            return;
+        }
+
+        if *LOGIN_SHELL.lock().unwrap() {
+            job_list.hangup();
+        }
     }
 
+    process::exit(status);
+}
+
+/* Signals from the terminal that should only reach the forground job */
+const JOB_CONTROL_SIGNALS: &[libc::c_int] = &[
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/* Sets up an interactive shell to do job control. The shell waits until it is
+ * in the forground, then moves into a process group of its own and takes the
+ * terminal for it. */
+fn init_job_control() {
     unsafe {
-        let cs = CString::new(CURRENT_DIR.lock().unwrap().clone()).unwrap();
-        libc::chdir(cs.as_ptr());
+        loop {
+            let pgrp = libc::getpgrp();
+
+            if libc::tcgetpgrp(libc::STDIN_FILENO) == pgrp {
+                break;
+            }
+
+            libc::kill(-pgrp, libc::SIGTTIN);
+        }
+
+        for sig in JOB_CONTROL_SIGNALS {
+            libc::signal(*sig, libc::SIG_IGN);
+        }
+
+        /* This fails if the shell already leads its session, in which case it
+         * leads its process group too */
+        let pid = libc::getpid();
+        libc::setpgid(pid, pid);
+
+        libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
     }
 
+    JOB_LIST.lock().unwrap().shell_tmodes = TermModes::get();
+}
+
+/* Reads and runs commands from stdin, a line at a time. The prompts are only
+ * printed when the shell is interactive. The end of the input is the same as
+ * 'exit'. */
+fn run_input(interactive: bool) -> ! {
+    let mut reader = InputReader::new();
+
     loop {
-        print!("{}: ", *CURRENT_DIR.lock().unwrap());
-        out.flush().unwrap();
-
-        let s = lines.next().unwrap().unwrap();
-
-        if s != "" {
-            let mut lexer = InputLexer::new(&s);
-            match Job::parse_job(&mut lexer) {
-                Some(mut job) => {
-                    job.name = s.clone();
-
-                    if job.is_simple_bulitin() {
-                        job.simple_builtin_run();
-                    } else {
-                        new_job = JOB_LIST.lock().unwrap().add_job(job);
-
-                        if !new_job.lock().unwrap().is_background {
-                            JOB_LIST.lock().unwrap().set_forground_job(Some(new_job));
-                        } else {
-                            JOB_LIST.lock().unwrap().set_forground_job(None);
-                        }
+        let prompt = if interactive {
+            let mut job_list = JOB_LIST.lock().unwrap();
+            job_list.restore_shell_modes();
+            job_list.print_notices();
+
+            format!("{}: ", *CURRENT_DIR.lock().unwrap())
+        } else {
+            String::new()
+        };
+
+        let mut s = match reader.read_line(&prompt) {
+            Some(line) => line,
+            None => {
+                let status = *LAST_STATUS.lock().unwrap();
+                exit_shell(status);
+                continue;
+            },
+        };
+
+        /* The warning about stopped jobs only holds off an exit that comes
+         * right after it */
+        let warned = JOB_LIST.lock().unwrap().stopped_warning;
+
+        loop {
+            let result = Parser::new(InputLexer::new(&s)).parse_program();
+
+            match result {
+                Ok(list) => {
+                    exec::run_list(&list);
+                    break;
+                },
+                Err(ParseError::Incomplete) => {
+                    match reader.read_line(if interactive { "> " } else { "" }) {
+                        Some(line) => {
+                            s += "\n";
+                            s += &line;
+                        },
+                        None => {
+                            eprintln!("rshell: {}", ParseError::Incomplete);
+                            exit_shell(2);
+                            break;
+                        },
                     }
                 },
-                None => {
-                    println!("rshell: Syntax error in command");
+                Err(err) => {
+                    eprintln!("rshell: {}", err);
+                    *LAST_STATUS.lock().unwrap() = 2;
+                    break;
                 }
             }
         }
 
-        JOB_LIST.lock().unwrap().update_job_list();
+        let mut job_list = JOB_LIST.lock().unwrap();
+        if warned {
+            job_list.stopped_warning = false;
+        }
+        job_list.update_job_list();
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let shell_name = args.first().cloned().unwrap_or_else(|| "rshell".to_string());
+
+    if args.get(1).map(|s| s == "-l" || s == "--login") == Some(true) {
+        args.remove(1);
+        *LOGIN_SHELL.lock().unwrap() = true;
+    }
+    if shell_name.starts_with('-') {
+        *LOGIN_SHELL.lock().unwrap() = true;
+    }
+
+    VARIABLES.lock().unwrap().import_env();
+    *SHELL_PID.lock().unwrap() = unsafe { libc::getpid() };
+    *SHELL_NAME.lock().unwrap() = shell_name.clone();
+
+    /* 'rshell -c command [name [args...]]' runs the command, and
+     * 'rshell file [args...]' runs the file as a script */
+    let script = match args.get(1).map(|s| s.as_str()) {
+        Some("-c") => {
+            let command = match args.get(2) {
+                Some(command) => command.clone(),
+                None => {
+                    eprintln!("rshell: -c: option requires an argument");
+                    process::exit(2);
+                },
+            };
+
+            if let Some(name) = args.get(3) {
+                *SHELL_NAME.lock().unwrap() = name.clone();
+            }
+            *POSITIONAL_PARAMS.lock().unwrap() = args.iter().skip(4).cloned().collect();
+
+            Some(("-c".to_string(), command))
+        },
+        Some(path) => {
+            let contents = match std::fs::read(path) {
+                Ok(contents) => String::from_utf8_lossy(&contents).into_owned(),
+                Err(err) => {
+                    eprintln!("rshell: {}: {}", path, exec::strerror(err.raw_os_error().unwrap_or(0)));
+                    process::exit(127);
+                },
+            };
+
+            *SHELL_NAME.lock().unwrap() = path.to_string();
+            *POSITIONAL_PARAMS.lock().unwrap() = args[2..].to_vec();
+
+            Some((path.to_string(), contents))
+        },
+        None => None,
+    };
+
+    let interactive = script.is_none() && unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+
+    /* Without a terminal to hand out, every job stays in the shell's own
+     * process group */
+    JOB_LIST.lock().unwrap().job_control = interactive;
+
+    signal::record_dispositions();
+
+    if interactive {
+        init_job_control();
+        watch_children();
+
+        match std::env::vars().find(|x| x.0 == "HOME") {
+            Some((_, cwd)) => {
+                *CURRENT_DIR.lock().unwrap() =  cwd;
+            },
+            _ => { *CURRENT_DIR.lock().unwrap() = "/".to_string() }
+        }
+
+        unsafe {
+            let cs = CString::new(CURRENT_DIR.lock().unwrap().clone()).unwrap();
+            libc::chdir(cs.as_ptr());
+        }
+    } else {
+        *CURRENT_DIR.lock().unwrap() = std::env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "/".to_string());
+    }
+
+    match script {
+        Some((name, source)) => {
+            let status = exec::run_source(&name, &source);
+            exit_shell(status);
+        },
+        None => run_input(interactive),
     }
 }
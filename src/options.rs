@@ -0,0 +1,50 @@
+use std::collections::*;
+
+/* The names of the options that can be turned on and off with 'set -o' */
+pub const OPTION_NAMES: &[&str] = &[
+    "failglob",
+    "globstar",
+    "noclobber",
+    "notify",
+    "nullglob",
+];
+
+/* Options that also have a single letter form, as in 'set -C' */
+pub const OPTION_LETTERS: &[(char, &str)] = &[
+    ('C', "noclobber"),
+    ('b', "notify"),
+];
+
+/* The shell options that are currently turned on */
+pub struct ShellOptions {
+    enabled: HashSet<&'static str>,
+}
+
+impl ShellOptions {
+    pub fn new() -> ShellOptions {
+        ShellOptions {
+            enabled: HashSet::new(),
+        }
+    }
+
+    pub fn is_set(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    /* Turns an option on or off, returning false if there is no option with
+     * that name */
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        let name = match OPTION_NAMES.iter().find(|n| **n == name) {
+            Some(name) => *name,
+            None => return false,
+        };
+
+        if on {
+            self.enabled.insert(name);
+        } else {
+            self.enabled.remove(name);
+        }
+
+        true
+    }
+}
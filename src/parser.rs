@@ -0,0 +1,511 @@
+use std::fmt;
+use std::iter::*;
+use lexer::*;
+use ast::*;
+use var::*;
+
+#[derive(PartialEq, Debug)]
+pub enum ParseError {
+    /* The input ended in the middle of a command, and more lines are needed
+     * to complete it */
+    Incomplete,
+    UnexpectedToken(InputToken),
+}
+
+impl ParseError {
+    /* An unterminated quote is never an error on its own, as the rest of it
+     * can be on the following lines */
+    fn from_token(tok: InputToken) -> ParseError {
+        if tok == InputToken::Unterminated {
+            ParseError::Incomplete
+        } else {
+            ParseError::UnexpectedToken(tok)
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::Incomplete => write!(f, "syntax error: unexpected end of file"),
+            ParseError::UnexpectedToken(ref tok) => write!(f, "syntax error near unexpected token `{}'", tok),
+        }
+    }
+}
+
+/* Reserved words that end a compound command's list, and so can never start a
+ * command */
+const CLOSING_WORDS: [&str; 7] = ["then", "elif", "else", "fi", "do", "done", "}"];
+
+pub struct Parser<'a> {
+    tokens: Peekable<InputLexer<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(lex: InputLexer<'a>) -> Parser<'a> {
+        Parser { tokens: lex.peekable() }
+    }
+
+    /* Parses the entire input as a list of commands */
+    pub fn parse_program(&mut self) -> Result<CommandList, ParseError> {
+        let list = self.parse_list(&[])?;
+
+        match self.next() {
+            Some(tok) => Err(ParseError::from_token(tok)),
+            None => Ok(list),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&InputToken> {
+        if self.tokens.peek() == Some(&InputToken::Comment) {
+            while self.tokens.peek().is_some() && self.tokens.peek() != Some(&InputToken::NewLine) {
+                self.tokens.next();
+            }
+        }
+
+        self.tokens.peek()
+    }
+
+    fn next(&mut self) -> Option<InputToken> {
+        self.peek();
+        self.tokens.next()
+    }
+
+    fn peek_reserved(&mut self, words: &[&str]) -> bool {
+        match self.peek() {
+            Some(InputToken::Identifier(word)) => match word.as_literal() {
+                Some(s) => words.contains(&s),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn unexpected(&mut self) -> ParseError {
+        match self.next() {
+            Some(tok) => ParseError::from_token(tok),
+            None => ParseError::Incomplete,
+        }
+    }
+
+    fn expect_reserved(&mut self, word: &str) -> Result<(), ParseError> {
+        if self.peek_reserved(&[word]) {
+            self.next();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&InputToken::NewLine) {
+            self.next();
+        }
+    }
+
+    /* Parses and-or lists separated by ';', '&' or newlines, until the end of the
+     * input, a ')', or one of the reserved words in 'terminators' */
+    fn parse_list(&mut self, terminators: &[&str]) -> Result<CommandList, ParseError> {
+        let mut list = CommandList::new();
+
+        loop {
+            self.skip_newlines();
+
+            if self.peek().is_none()
+                || self.peek() == Some(&InputToken::RightParen)
+                || self.peek_reserved(terminators) {
+                break;
+            }
+
+            let and_or = self.parse_and_or()?;
+            let mut item = ListItem { and_or, is_background: false };
+
+            match self.peek() {
+                Some(&InputToken::Background) => {
+                    self.next();
+                    item.is_background = true;
+                },
+                Some(&InputToken::Semicolon) => {
+                    self.next();
+                },
+                Some(&InputToken::NewLine) | Some(&InputToken::RightParen) | None => { },
+                _ => {
+                    if !self.peek_reserved(terminators) {
+                        return Err(self.unexpected());
+                    }
+                },
+            }
+
+            list.items.push(item);
+        }
+
+        Ok(list)
+    }
+
+    /* A list inside of a compound command, which may not be empty */
+    fn parse_compound_list(&mut self, terminators: &[&str]) -> Result<CommandList, ParseError> {
+        let list = self.parse_list(terminators)?;
+
+        if list.is_empty() {
+            return Err(self.unexpected());
+        }
+
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut and_or = AndOr { first, rest: Vec::new() };
+
+        loop {
+            let op = match self.peek() {
+                Some(&InputToken::LogicAnd) => AndOrOp::And,
+                Some(&InputToken::LogicOr) => AndOrOp::Or,
+                _ => break,
+            };
+
+            self.next();
+            self.skip_newlines();
+
+            let pipeline = self.parse_pipeline()?;
+            and_or.rest.push((op, pipeline));
+        }
+
+        Ok(and_or)
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut pipeline = Pipeline { negate: false, commands: Vec::new() };
+
+        if self.peek_reserved(&["!"]) {
+            self.next();
+            pipeline.negate = true;
+        }
+
+        pipeline.commands.push(self.parse_command()?);
+
+        while self.peek() == Some(&InputToken::Pipe) {
+            self.next();
+            self.skip_newlines();
+
+            pipeline.commands.push(self.parse_command()?);
+        }
+
+        Ok(pipeline)
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        let compound = if self.peek() == Some(&InputToken::LeftParen) {
+            self.next();
+
+            let list = self.parse_compound_list(&[])?;
+
+            if self.peek() != Some(&InputToken::RightParen) {
+                return Err(self.unexpected());
+            }
+            self.next();
+
+            CompoundCommand::Subshell(list)
+        } else if self.peek_reserved(&["{"]) {
+            self.next();
+
+            let list = self.parse_compound_list(&["}"])?;
+            self.expect_reserved("}")?;
+
+            CompoundCommand::BraceGroup(list)
+        } else if self.peek_reserved(&["if"]) {
+            self.parse_if()?
+        } else if self.peek_reserved(&["while", "until"]) {
+            self.parse_loop()?
+        } else if self.peek().is_none() || self.peek_reserved(&CLOSING_WORDS) {
+            return Err(self.unexpected());
+        } else {
+            return self.parse_simple_command().map(Command::Simple);
+        };
+
+        let mut redirects = Vec::new();
+
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
+
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    fn parse_if(&mut self) -> Result<CompoundCommand, ParseError> {
+        let mut branches = Vec::new();
+        let mut else_part = None;
+
+        self.next();
+
+        loop {
+            let cond = self.parse_compound_list(&["then"])?;
+            self.expect_reserved("then")?;
+            let body = self.parse_compound_list(&["elif", "else", "fi"])?;
+
+            branches.push((cond, body));
+
+            if !self.peek_reserved(&["elif"]) {
+                break;
+            }
+            self.next();
+        }
+
+        if self.peek_reserved(&["else"]) {
+            self.next();
+            else_part = Some(self.parse_compound_list(&["fi"])?);
+        }
+
+        self.expect_reserved("fi")?;
+
+        Ok(CompoundCommand::If(branches, else_part))
+    }
+
+    fn parse_loop(&mut self) -> Result<CompoundCommand, ParseError> {
+        let is_while = self.peek_reserved(&["while"]);
+        self.next();
+
+        let cond = self.parse_compound_list(&["do"])?;
+        self.expect_reserved("do")?;
+        let body = self.parse_compound_list(&["done"])?;
+        self.expect_reserved("done")?;
+
+        if is_while {
+            Ok(CompoundCommand::While(cond, body))
+        } else {
+            Ok(CompoundCommand::Until(cond, body))
+        }
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut cmd = SimpleCommand { assignments: Vec::new(), words: Vec::new(), redirects: Vec::new() };
+
+        loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                cmd.redirects.push(redirect);
+                continue;
+            }
+
+            match self.peek() {
+                Some(&InputToken::Identifier(_)) => { },
+                _ => break,
+            }
+
+            if let Some(InputToken::Identifier(word)) = self.next() {
+                /* Assignments are only recognized before the command name */
+                if cmd.words.is_empty() {
+                    if let Some(assignment) = parse_assignment(&word) {
+                        cmd.assignments.push(assignment);
+                        continue;
+                    }
+                }
+
+                cmd.words.push(word);
+            }
+        }
+
+        if cmd.assignments.is_empty() && cmd.words.is_empty() && cmd.redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+
+        Ok(cmd)
+    }
+
+    /* Parses a redirection operator and its target, if there is one next */
+    fn parse_redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let fd = match self.peek() {
+            Some(&InputToken::IoNumber(fd)) => {
+                self.next();
+                Some(fd)
+            },
+            _ => None,
+        };
+
+        let kind = match self.peek() {
+            Some(&InputToken::RedirectIn) => RedirectKind::In,
+            Some(&InputToken::RedirectOut) => RedirectKind::Out,
+            Some(&InputToken::RedirectAppendOut) => RedirectKind::AppendOut,
+            Some(&InputToken::RedirectClobber) => RedirectKind::Clobber,
+            Some(&InputToken::RedirectReadWrite) => RedirectKind::ReadWrite,
+            Some(&InputToken::RedirectDupIn) => RedirectKind::DupIn,
+            Some(&InputToken::RedirectDupOut) => RedirectKind::DupOut,
+            Some(&InputToken::RedirectAllOut) if fd.is_none() => RedirectKind::AllOut,
+            Some(&InputToken::RedirectAllAppendOut) if fd.is_none() => RedirectKind::AllAppendOut,
+            Some(&InputToken::HereString) => RedirectKind::HereString,
+            Some(&InputToken::HereDoc(_, _)) => {
+                if let Some(InputToken::HereDoc(delim, body)) = self.next() {
+                    return Ok(Some(Redirect { fd, kind: RedirectKind::HereDoc(delim), target: body }));
+                }
+                unreachable!();
+            },
+            Some(&InputToken::HereDocOp) => {
+                self.next();
+
+                return match self.next() {
+                    Some(tok) => Err(ParseError::from_token(tok)),
+                    None => Err(ParseError::UnexpectedToken(InputToken::NewLine)),
+                };
+            },
+            /* The lexer only gives an fd number right before an operator */
+            _ if fd.is_some() => return Err(self.unexpected()),
+            _ => return Ok(None),
+        };
+
+        self.next();
+
+        match self.next() {
+            Some(InputToken::Identifier(target)) => Ok(Some(Redirect { fd, kind, target })),
+            Some(tok) => Err(ParseError::from_token(tok)),
+            None => Err(ParseError::UnexpectedToken(InputToken::NewLine)),
+        }
+    }
+}
+
+/* Splits a 'NAME=value' word into an assignment */
+fn parse_assignment(word: &Word) -> Option<Assignment> {
+    let (name, value) = match word.parts.first() {
+        Some(WordPart::Literal(s)) => {
+            let eq = s.find('=')?;
+            (&s[..eq], &s[eq + 1..])
+        },
+        _ => return None,
+    };
+
+    if !is_valid_name(name) {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+
+    if !value.is_empty() {
+        parts.push(WordPart::Literal(value.to_string()));
+    }
+    parts.extend(word.parts[1..].iter().cloned());
+
+    Some(Assignment { name: name.to_string(), value: Word { parts } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<CommandList, ParseError> {
+        Parser::new(InputLexer::new(s)).parse_program()
+    }
+
+    fn word(s: &str) -> Word {
+        Word { parts: vec![WordPart::Literal(s.to_string())] }
+    }
+
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(SimpleCommand {
+            assignments: Vec::new(),
+            words: words.iter().map(|w| word(w)).collect(),
+            redirects: Vec::new(),
+        })
+    }
+
+    fn pipeline(commands: Vec<Command>) -> Pipeline {
+        Pipeline { negate: false, commands }
+    }
+
+    fn item(and_or: AndOr, is_background: bool) -> ListItem {
+        ListItem { and_or, is_background }
+    }
+
+    fn single(command: Command) -> AndOr {
+        AndOr { first: pipeline(vec![command]), rest: Vec::new() }
+    }
+
+    fn list(commands: &[&[&str]]) -> CommandList {
+        CommandList { items: commands.iter().map(|c| item(single(simple(c)), false)).collect() }
+    }
+
+    fn compound(cmd: CompoundCommand) -> CommandList {
+        CommandList { items: vec![item(single(Command::Compound(cmd, Vec::new())), false)] }
+    }
+
+    #[test]
+    fn and_or_lists() {
+        let and_or = AndOr {
+            first: pipeline(vec![simple(&["a"])]),
+            rest: vec![
+                (AndOrOp::And, pipeline(vec![simple(&["b"])])),
+                (AndOrOp::Or, pipeline(vec![simple(&["c"])])),
+            ],
+        };
+
+        assert_eq!(parse("a && b || c"), Ok(CommandList { items: vec![item(and_or, false)] }));
+    }
+
+    #[test]
+    fn background_pipelines() {
+        let and_or = AndOr { first: pipeline(vec![simple(&["a"]), simple(&["b"])]), rest: Vec::new() };
+
+        assert_eq!(parse("a | b &"), Ok(CommandList { items: vec![item(and_or, true)] }));
+        assert_eq!(parse("a; b\nc"), Ok(list(&[&["a"], &["b"], &["c"]])));
+    }
+
+    #[test]
+    fn sequential_lists() {
+        assert_eq!(parse("make & tail -f log; echo done"), Ok(CommandList { items: vec![
+            item(single(simple(&["make"])), true),
+            item(single(simple(&["tail", "-f", "log"])), false),
+            item(single(simple(&["echo", "done"])), false),
+        ] }));
+        assert_eq!(parse("a;b;"), Ok(list(&[&["a"], &["b"]])));
+        assert_eq!(parse("; a"), Err(ParseError::UnexpectedToken(InputToken::Semicolon)));
+        assert_eq!(parse("a;;"), Err(ParseError::UnexpectedToken(InputToken::Semicolon)));
+    }
+
+    #[test]
+    fn compound_commands() {
+        assert_eq!(parse("if a; then b; elif c; then d; else e; fi"),
+            Ok(compound(CompoundCommand::If(
+                vec![(list(&[&["a"]]), list(&[&["b"]])), (list(&[&["c"]]), list(&[&["d"]]))],
+                Some(list(&[&["e"]]))))));
+        assert_eq!(parse("while a\ndo b; done"),
+            Ok(compound(CompoundCommand::While(list(&[&["a"]]), list(&[&["b"]])))));
+        assert_eq!(parse("until a; do b; done"),
+            Ok(compound(CompoundCommand::Until(list(&[&["a"]]), list(&[&["b"]])))));
+        assert_eq!(parse("(a; b)"),
+            Ok(compound(CompoundCommand::Subshell(list(&[&["a"], &["b"]])))));
+        assert_eq!(parse("{ a; b; }"),
+            Ok(compound(CompoundCommand::BraceGroup(list(&[&["a"], &["b"]])))));
+    }
+
+    #[test]
+    fn assignments_and_redirects() {
+        let cmd = SimpleCommand {
+            assignments: vec![Assignment { name: "X".to_string(), value: word("1") }],
+            words: vec![word("cmd"), word("Y=2")],
+            redirects: vec![
+                Redirect { fd: Some(2), kind: RedirectKind::Out, target: word("err") },
+                Redirect { fd: None, kind: RedirectKind::In, target: word("in") },
+            ],
+        };
+
+        assert_eq!(parse("X=1 cmd 2>err Y=2 <in"), Ok(CommandList { items: vec![item(single(Command::Simple(cmd)), false)] }));
+
+        let redirects = vec![Redirect { fd: None, kind: RedirectKind::Out, target: word("out") }];
+        let group = Command::Compound(CompoundCommand::BraceGroup(list(&[&["a"]])), redirects);
+
+        assert_eq!(parse("{ a; } >out"), Ok(CommandList { items: vec![item(single(group), false)] }));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        for s in &["if a; then", "if a; then b; else c", "while a; do b", "until a", "(a", "{ a;", "a &&", "a ||", "a |", "echo 'a"] {
+            assert_eq!(parse(s), Err(ParseError::Incomplete), "{}", s);
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(parse("| a"), Err(ParseError::UnexpectedToken(InputToken::Pipe)));
+        assert_eq!(parse("a &&;"), Err(ParseError::UnexpectedToken(InputToken::Semicolon)));
+        assert_eq!(parse("a )"), Err(ParseError::UnexpectedToken(InputToken::RightParen)));
+        assert_eq!(parse("then a"), Err(ParseError::UnexpectedToken(InputToken::Identifier(word("then")))));
+        assert_eq!(parse("()"), Err(ParseError::UnexpectedToken(InputToken::RightParen)));
+    }
+}
@@ -2,68 +2,256 @@
 use libc;
 use std::ffi::CString;
 use std::process;
+use std::ptr;
 use builtin::*;
+use ast::*;
+use exec;
+use signal;
+use var::*;
+use std::fs;
+use std::fmt;
+use ::*;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ExitStatus {
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl ExitStatus {
+    pub fn from_wait(wstatus: libc::c_int) -> ExitStatus {
+        if libc::WIFSIGNALED(wstatus) {
+            ExitStatus::Signaled(libc::WTERMSIG(wstatus))
+        } else {
+            ExitStatus::Exited(libc::WEXITSTATUS(wstatus))
+        }
+    }
+
+    /* The status as seen by '$?', where being killed by a signal is reported
+     * as 128 plus the signal number */
+    pub fn code(&self) -> i32 {
+        match *self {
+            ExitStatus::Exited(code) => code,
+            ExitStatus::Signaled(sig) => 128 + sig,
+        }
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExitStatus::Exited(0) => write!(f, "Done"),
+            ExitStatus::Exited(code) => write!(f, "Exit {}", code),
+            ExitStatus::Signaled(sig) => write!(f, "Killed ({})", signal::signal_name(sig)),
+        }
+    }
+}
+
+/* What a redirected fd is pointed at */
+#[derive(Debug)]
+pub enum FdSource {
+    /* A file or pipe the shell opened for the prog, which the prog owns */
+    File(libc::c_int),
+    /* A copy of another fd, as it is at that point in the redirections */
+    Dup(libc::c_int),
+    Close,
+}
+
+#[derive(Debug)]
+pub struct FdRedirect {
+    pub fd: libc::c_int,
+    pub source: FdSource,
+}
+
+impl FdRedirect {
+    /* Points the fd at its source, returning false if the source isn't an
+     * open fd */
+    pub unsafe fn apply(&self) -> bool {
+        match self.source {
+            /* The shell opens files close-on-exec, which dup2() clears on the
+             * copy */
+            FdSource::File(src) if src == self.fd => libc::fcntl(src, libc::F_SETFD, 0) != -1,
+            FdSource::Dup(src) if src == self.fd => libc::fcntl(src, libc::F_GETFD) != -1,
+            FdSource::File(src) | FdSource::Dup(src) => libc::dup2(src, self.fd) != -1,
+            FdSource::Close => {
+                libc::close(self.fd);
+                true
+            },
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Prog {
+    /* The text of the command, as shown by 'jobs -l' */
+    pub name: String,
     pub file: String,
     pub args: Vec<String>,
-    pub stdin: libc::c_int,
-    pub stdout: libc::c_int,
-    pub stderr: libc::c_int,
+    /* 'NAME=value' assignments given before the command name */
+    pub assignments: Vec<(String, String)>,
+    /* Redirections, including pipes, applied in order when the prog starts */
+    pub redirects: Vec<FdRedirect>,
     pub pgrp: libc::pid_t,
     pub pid: libc::pid_t,
+    pub status: Option<ExitStatus>,
     pub builtin: Option<ShellBuiltin>,
-}
-
-unsafe fn dup_if_not_eq(old_fd: libc::c_int, new_fd: libc::c_int) {
-    if old_fd != new_fd {
-        libc::dup2(new_fd, old_fd);
-        libc::close(new_fd);
-    }
+    /* A compound command run by a forked copy of the shell in place of 'file' */
+    pub body: Option<CompoundCommand>,
+    /* For a command with no name, the status of the last command substitution
+     * in it, if it had any */
+    pub subst_status: Option<i32>,
+    /* Set for the subshell of a command substitution. It stays in the shell's
+     * process group, so it ignores the stop signals from the terminal that the
+     * shell ignores as well. */
+    pub is_command_subst: bool,
+    /* Set when expanding the command or opening its redirections failed, once
+     * the error has been reported. It exits with status 1 in its place in the
+     * pipeline, without running anything. */
+    pub failed: bool,
+    pub is_background: bool,
 }
 
 impl Prog {
     pub fn new() -> Prog {
         Prog {
+            name: "".to_string(),
             file: "".to_string(),
             args: Vec::new(),
-            stdin: libc::STDIN_FILENO,
-            stdout: libc::STDOUT_FILENO,
-            stderr: libc::STDERR_FILENO,
+            assignments: Vec::new(),
+            redirects: Vec::new(),
             pgrp: -1,
             pid: -1,
+            status: None,
             builtin: None,
+            body: None,
+            subst_status: None,
+            is_command_subst: false,
+            failed: false,
+            is_background: false,
         }
     }
 
-    pub fn close_fds(&mut self) {
-        if self.stdin != libc::STDIN_FILENO {
-            unsafe { libc::close(self.stdin); }
+    pub fn add_redirect(&mut self, fd: libc::c_int, source: FdSource) {
+        self.redirects.push(FdRedirect { fd, source });
+    }
+
+    /* The fd that 'fd' refers to after the first 'end' redirections */
+    fn fd_at(&self, fd: libc::c_int, end: usize) -> libc::c_int {
+        match self.redirects[..end].iter().rposition(|r| r.fd == fd) {
+            None => fd,
+            Some(i) => match self.redirects[i].source {
+                FdSource::File(src) => src,
+                FdSource::Dup(src) => self.fd_at(src, i),
+                FdSource::Close => -1,
+            },
         }
+    }
+
+    /* The shell's fd that the prog uses as 'fd', for builtins that run in the
+     * shell without applying the redirections. It's -1 if the fd is
+     * closed. */
+    pub fn fd(&self, fd: libc::c_int) -> libc::c_int {
+        self.fd_at(fd, self.redirects.len())
+    }
 
-        if self.stdout != libc::STDOUT_FILENO {
-            unsafe { libc::close(self.stdout); }
+    /* The first fd copied by a redirection that isn't open, or that the shell
+     * keeps for itself, if any */
+    pub fn bad_dup(&self) -> Option<libc::c_int> {
+        for (i, redirect) in self.redirects.iter().enumerate() {
+            if let FdSource::Dup(src) = redirect.source {
+                let fd = self.fd_at(src, i);
+
+                if fd == -1 || job_list::is_child_pipe(fd) || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                    return Some(src);
+                }
+            }
         }
 
-        if self.stderr != libc::STDERR_FILENO {
-            unsafe { libc::close(self.stderr); }
+        None
+    }
+
+    pub fn stdout(&self) -> libc::c_int {
+        self.fd(libc::STDOUT_FILENO)
+    }
+
+    pub fn stderr(&self) -> libc::c_int {
+        self.fd(libc::STDERR_FILENO)
+    }
+
+    /* Closes the files opened for the prog, once they've been given to it */
+    pub fn close_fds(&mut self) {
+        for redirect in self.redirects.drain(..) {
+            if let FdSource::File(src) = redirect.source {
+                unsafe { libc::close(src); }
+            }
         }
     }
 
     unsafe fn start_child(&mut self) {
-        libc::setpgid(0, self.pgrp);
+        if self.pgrp != -1 {
+            libc::setpgid(0, self.pgrp);
+        }
 
-        dup_if_not_eq(libc::STDIN_FILENO, self.stdin);
-        dup_if_not_eq(libc::STDOUT_FILENO, self.stdout);
-        dup_if_not_eq(libc::STDERR_FILENO, self.stderr);
+        if self.failed {
+            process::exit(1);
+        }
 
-        match self.builtin {
-            Some(builtin) => {
-                let ret = (builtin.callback) (self);
-                process::exit(ret as i32);
-            },
-            _ => { }
+        /* The signals go back to their defaults, including for builtins and
+         * subshells so that they can be interrupted. Without job control, a
+         * background job ignores the terminal's interrupt and quit signals,
+         * which it would get as well. */
+        if JOB_LIST.lock().unwrap().job_control {
+            signal::restore_dispositions();
+        } else if self.is_background {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+            libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        }
+
+        job_list::close_child_pipe();
+
+        /* The Rust runtime ignores SIGPIPE in the shell itself, which the
+         * commands it runs don't expect */
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+
+        if self.is_command_subst {
+            for sig in &[libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+                libc::signal(*sig, libc::SIG_IGN);
+            }
+        }
+
+        for redirect in &self.redirects {
+            if !redirect.apply() {
+                if let FdSource::Dup(src) = redirect.source {
+                    eprintln!("rshell: {}: Bad file descriptor", src);
+                }
+                process::exit(1);
+            }
+        }
+
+        /* The opened files are closed, unless one of them was redirected to
+         * itself */
+        let targets: Vec<libc::c_int> = self.redirects.iter().map(|r| r.fd).collect();
+
+        for redirect in self.redirects.drain(..) {
+            if let FdSource::File(src) = redirect.source {
+                if !targets.contains(&src) {
+                    libc::close(src);
+                }
+            }
+        }
+
+        if let Some(builtin) = self.builtin {
+            let ret = (builtin.callback) (self);
+            process::exit(ret);
+        }
+
+        if let Some(ref body) = self.body {
+            exec::enter_subshell();
+            process::exit(exec::run_compound(body));
+        }
+
+        if self.file.is_empty() {
+            process::exit(0);
         }
 
         let mut cstr_vec: Vec<CString> = Vec::new();
@@ -80,29 +268,131 @@ impl Prog {
         for s in &mut cstr_vec {
             c_char_vec.push(s.as_ptr());
         }
-        c_char_vec.push(0 as *const libc::c_char);
+        c_char_vec.push(ptr::null());
+
+        let env_vec: Vec<CString> = self.environment().iter()
+                                        .map(|(name, value)| CString::new(format!("{}={}", name, value)).unwrap())
+                                        .collect();
+
+        let mut envp: Vec<*const libc::c_char> = env_vec.iter().map(|s| s.as_ptr()).collect();
+        envp.push(ptr::null());
+
+        /* A file that was found but couldn't be run */
+        let mut denied: Option<String> = None;
+
+        for path in self.exec_paths() {
+            let cpath = CString::new(path.clone()).unwrap();
+            libc::execve(cpath.as_ptr(), c_char_vec.as_ptr(), envp.as_ptr());
+
+            match exec::last_errno() {
+                libc::ENOEXEC => self.run_script(&path),
+                libc::EACCES if denied.is_none() => denied = Some(path),
+                _ => { },
+            }
+        }
+
+        let (message, status) = self.exec_failure(denied);
+        eprintln!("rshell: {}", message);
+        process::exit(status);
+    }
 
-        for i in 0..32 {
-            libc::signal(i, libc::SIG_DFL);
+    /* What to report when the prog couldn't be run, and the status to exit
+     * with: 126 if 'denied' is a file that was found but couldn't be run, and
+     * 127 if nothing was found */
+    fn exec_failure(&self, denied: Option<String>) -> (String, i32) {
+        match denied {
+            Some(path) => {
+                let is_dir = fs::metadata(&path).map(|m| m.is_dir()).unwrap_or(false);
+                (format!("{}: {}", path, if is_dir { "Is a directory" } else { "Permission denied" }), 126)
+            },
+            None if self.file.contains('/') => (format!("{}: No such file or directory", self.file), 127),
+            None => (format!("{}: command not found", self.file), 127),
         }
+    }
+
+    /* Runs a file without a '#!' line as a shell script, in the child that
+     * was going to exec it. The script starts out with only the environment
+     * as its variables, and the args as its positional parameters. */
+    fn run_script(&self, path: &str) -> ! {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("rshell: {}: {}", path, exec::strerror(err.raw_os_error().unwrap_or(0)));
+                process::exit(126);
+            },
+        };
 
-        libc::execvp(CString::new(self.file.clone()).unwrap().as_ptr() as *const libc::c_char, c_char_vec.as_ptr() as *const *const libc::c_char);
+        let first_line = contents.split(|b| *b == b'\n').next().unwrap_or(&[]);
+        if first_line.contains(&0) {
+            eprintln!("rshell: {}: cannot execute binary file", path);
+            process::exit(126);
+        }
 
+        let contents = String::from_utf8_lossy(&contents).into_owned();
+        let env = self.environment();
 
-        println!("{}: command not found", self.file);
-        process::exit(0);
+        exec::enter_subshell();
+        *SHELL_NAME.lock().unwrap() = path.to_string();
+
+        {
+            let mut vars = VARIABLES.lock().unwrap();
+            *vars = VarStore::new();
+
+            for (name, value) in env {
+                vars.set(&name, &value);
+                vars.export(&name);
+            }
+        }
+
+        *POSITIONAL_PARAMS.lock().unwrap() = self.args.clone();
+
+        process::exit(exec::run_source(path, &contents));
+    }
+
+    /* The exported variables, plus the assignments given to this prog, sorted
+     * by name */
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut env = VARIABLES.lock().unwrap().environment();
+
+        for (name, value) in &self.assignments {
+            env.retain(|(n, _)| n != name);
+            env.push((name.clone(), value.clone()));
+        }
+
+        env.sort();
+        env
+    }
+
+    /* The paths to try executing 'file' from, searching PATH if it has no
+     * slash in it */
+    fn exec_paths(&self) -> Vec<String> {
+        if self.file.contains('/') {
+            return vec![self.file.clone()];
+        }
+
+        let path = match self.assignments.iter().find(|(name, _)| name == "PATH") {
+            Some((_, value)) => value.clone(),
+            None => VARIABLES.lock().unwrap().get("PATH").unwrap_or("/usr/local/bin:/usr/bin:/bin").to_string(),
+        };
+
+        path.split(':')
+            .map(|dir| if dir.is_empty() { "." } else { dir })
+            .map(|dir| format!("{}/{}", dir, self.file))
+            .collect()
     }
 
     pub fn run(&mut self) {
         self.pid = unsafe { libc::fork() };
 
         match self.pid {
-            -1 => return,
+            -1 => { },
             0 => unsafe { self.start_child() },
             _ => {
                 /* Parent - close fd's */
                 unsafe {
-                    libc::setpgid(self.pid, self.pgrp);
+                    if self.pgrp != -1 {
+                        libc::setpgid(self.pid, self.pgrp);
+                    }
                     self.close_fds();
                 }
             }
@@ -113,14 +403,11 @@ impl Prog {
         self.args.push(s.to_string());
     }
 
-    pub fn run_builtin(&mut self) -> usize {
-        let mut ret: usize = 0;
+    pub fn run_builtin(&mut self) -> i32 {
+        let mut ret: i32 = 0;
 
-        match self.builtin {
-            Some(builtin) => {
-                ret = (builtin.callback) (self);
-            },
-            _ => { }
+        if let Some(builtin) = self.builtin {
+            ret = (builtin.callback) (self);
         }
 
         self.close_fds();
@@ -129,3 +416,68 @@ impl Prog {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_statuses() {
+        assert_eq!(ExitStatus::Exited(0).to_string(), "Done");
+        assert_eq!(ExitStatus::Exited(3).to_string(), "Exit 3");
+        assert_eq!(ExitStatus::Signaled(libc::SIGTERM).to_string(), "Killed (SIGTERM)");
+        assert_eq!(ExitStatus::Signaled(libc::SIGKILL).code(), 137);
+        assert_eq!(ExitStatus::Exited(3).code(), 3);
+    }
+
+    #[test]
+    fn wait_statuses() {
+        assert_eq!(ExitStatus::from_wait(0), ExitStatus::Exited(0));
+        assert_eq!(ExitStatus::from_wait(3 << 8), ExitStatus::Exited(3));
+        assert_eq!(ExitStatus::from_wait(libc::SIGTERM), ExitStatus::Signaled(libc::SIGTERM));
+
+        /* With a core dump */
+        assert_eq!(ExitStatus::from_wait(libc::SIGSEGV | 0x80), ExitStatus::Signaled(libc::SIGSEGV));
+    }
+
+    #[test]
+    fn command_paths() {
+        let mut prog = Prog::new();
+
+        /* A name with a slash in it isn't looked for in PATH */
+        prog.file = "./script".to_string();
+        assert_eq!(prog.exec_paths(), vec!["./script"]);
+
+        /* An empty entry in PATH is the current directory */
+        prog.file = "ls".to_string();
+        prog.assignments.push(("PATH".to_string(), "/bin::/usr/bin".to_string()));
+        assert_eq!(prog.exec_paths(), vec!["/bin/ls", "./ls", "/usr/bin/ls"]);
+    }
+
+    #[test]
+    fn exec_failures() {
+        let mut prog = Prog::new();
+
+        prog.file = "nope".to_string();
+        assert_eq!(prog.exec_failure(None), ("nope: command not found".to_string(), 127));
+
+        prog.file = "./nope".to_string();
+        assert_eq!(prog.exec_failure(None), ("./nope: No such file or directory".to_string(), 127));
+
+        assert_eq!(prog.exec_failure(Some("/".to_string())), ("/: Is a directory".to_string(), 126));
+        assert_eq!(prog.exec_failure(Some("/etc/passwd".to_string())), ("/etc/passwd: Permission denied".to_string(), 126));
+    }
+
+    #[test]
+    fn prog_environment() {
+        let mut prog = Prog::new();
+        prog.assignments.push(("RSHELL_B".to_string(), "2".to_string()));
+        prog.assignments.push(("RSHELL_A".to_string(), "1".to_string()));
+
+        let env: Vec<(String, String)> = prog.environment().into_iter()
+            .filter(|(name, _)| name.starts_with("RSHELL_"))
+            .collect();
+
+        assert_eq!(env, vec![("RSHELL_A".to_string(), "1".to_string()),
+                             ("RSHELL_B".to_string(), "2".to_string())]);
+    }
+}
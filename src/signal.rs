@@ -0,0 +1,177 @@
+use libc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::mem;
+use std::ptr;
+
+/* The standard signals by name, without the 'SIG' in front */
+const SIGNALS: &[(libc::c_int, &str)] = &[
+    (libc::SIGHUP, "HUP"),
+    (libc::SIGINT, "INT"),
+    (libc::SIGQUIT, "QUIT"),
+    (libc::SIGILL, "ILL"),
+    (libc::SIGTRAP, "TRAP"),
+    (libc::SIGABRT, "ABRT"),
+    (libc::SIGBUS, "BUS"),
+    (libc::SIGFPE, "FPE"),
+    (libc::SIGKILL, "KILL"),
+    (libc::SIGUSR1, "USR1"),
+    (libc::SIGSEGV, "SEGV"),
+    (libc::SIGUSR2, "USR2"),
+    (libc::SIGPIPE, "PIPE"),
+    (libc::SIGALRM, "ALRM"),
+    (libc::SIGTERM, "TERM"),
+    #[cfg(target_os = "linux")]
+    (libc::SIGSTKFLT, "STKFLT"),
+    (libc::SIGCHLD, "CHLD"),
+    (libc::SIGCONT, "CONT"),
+    (libc::SIGSTOP, "STOP"),
+    (libc::SIGTSTP, "TSTP"),
+    (libc::SIGTTIN, "TTIN"),
+    (libc::SIGTTOU, "TTOU"),
+    (libc::SIGURG, "URG"),
+    (libc::SIGXCPU, "XCPU"),
+    (libc::SIGXFSZ, "XFSZ"),
+    (libc::SIGVTALRM, "VTALRM"),
+    (libc::SIGPROF, "PROF"),
+    (libc::SIGWINCH, "WINCH"),
+    (libc::SIGIO, "IO"),
+    #[cfg(target_os = "linux")]
+    (libc::SIGPWR, "PWR"),
+    (libc::SIGSYS, "SYS"),
+];
+
+/* The name of a signal such as 'SIGTERM' or 'SIGRTMIN+2', or just its number
+ * if it has no name */
+pub fn signal_name(sig: libc::c_int) -> String {
+    match SIGNALS.iter().find(|(num, _)| *num == sig) {
+        Some((_, name)) => format!("SIG{}", name),
+        None if sig == libc::SIGRTMIN() => "SIGRTMIN".to_string(),
+        None if sig == libc::SIGRTMAX() => "SIGRTMAX".to_string(),
+        None if sig > libc::SIGRTMIN() && sig < libc::SIGRTMAX() => format!("SIGRTMIN+{}", sig - libc::SIGRTMIN()),
+        None => sig.to_string(),
+    }
+}
+
+/* The signal with a name like 'TERM', 'SIGTERM', 'term' or 'RTMIN+2', or a
+ * number */
+pub fn signal_number(name: &str) -> Option<libc::c_int> {
+    if let Ok(sig) = name.parse::<libc::c_int>() {
+        return if sig >= 0 && sig <= libc::SIGRTMAX() { Some(sig) } else { None };
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+
+    if let Some(&(sig, _)) = SIGNALS.iter().find(|(_, n)| *n == name) {
+        return Some(sig);
+    }
+
+    let (base, offset) = match name.find(['+', '-']) {
+        Some(pos) => (&name[..pos], name[pos..].parse::<libc::c_int>().ok()?),
+        None => (name, 0),
+    };
+
+    let sig = match base {
+        "RTMIN" => libc::SIGRTMIN() + offset,
+        "RTMAX" => libc::SIGRTMAX() + offset,
+        _ => return None,
+    };
+
+    if sig >= libc::SIGRTMIN() && sig <= libc::SIGRTMAX() {
+        Some(sig)
+    } else {
+        None
+    }
+}
+
+/* Every signal number that has a name, for 'kill -l' */
+pub fn all_signals() -> Vec<libc::c_int> {
+    let mut sigs: Vec<libc::c_int> = SIGNALS.iter().map(|(sig, _)| *sig).collect();
+
+    sigs.extend(libc::SIGRTMIN()..libc::SIGRTMAX() + 1);
+    sigs.sort();
+    sigs
+}
+
+/* Which signals were ignored when the shell started, bit 'sig - 1' for each
+ * signal number */
+static IGNORED_AT_START: AtomicU64 = AtomicU64::new(0);
+
+/* Records which signals the shell was started with ignored, before it
+ * changes any of them */
+pub fn record_dispositions() {
+    let mut ignored = 0;
+
+    for sig in 1..libc::SIGRTMAX() + 1 {
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+
+            if libc::sigaction(sig, ptr::null(), &mut action) == 0 && action.sa_sigaction == libc::SIG_IGN {
+                ignored |= 1 << (sig - 1);
+            }
+        }
+    }
+
+    IGNORED_AT_START.store(ignored, Ordering::Relaxed);
+}
+
+/* Resets every signal up to SIGRTMAX to its default, in a child that runs a
+ * command. A signal that was ignored from the start stays ignored, as with
+ * 'nohup', since the user asked for that and not the shell. */
+pub fn restore_dispositions() {
+    let ignored = IGNORED_AT_START.load(Ordering::Relaxed);
+
+    for sig in 1..libc::SIGRTMAX() + 1 {
+        if sig == libc::SIGKILL || sig == libc::SIGSTOP {
+            continue;
+        }
+
+        let handler = if ignored & (1 << (sig - 1)) != 0 { libc::SIG_IGN } else { libc::SIG_DFL };
+        unsafe { libc::signal(sig, handler); }
+    }
+}
+
+/* Set by the SIGINT handler, which can't take a lock */
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn note_interrupt(_sig: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/* Makes Ctrl-C interrupt an interactive shell's system calls instead of being
+ * ignored, as while 'wait' blocks */
+pub fn catch_interrupt() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = note_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, ptr::null_mut());
+    }
+}
+
+pub fn ignore_interrupt() {
+    unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN); }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_numbers() {
+        assert_eq!(signal_name(libc::SIGTERM), "SIGTERM");
+        assert_eq!(signal_number("TERM"), Some(libc::SIGTERM));
+        assert_eq!(signal_number("sigkill"), Some(libc::SIGKILL));
+        assert_eq!(signal_number("9"), Some(9));
+        assert_eq!(signal_number("BOGUS"), None);
+        assert_eq!(signal_number("RTMIN+2"), Some(libc::SIGRTMIN() + 2));
+        assert_eq!(signal_name(libc::SIGRTMIN() + 2), "SIGRTMIN+2");
+        assert_eq!(signal_number("RTMAX+1"), None);
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::*;
+use std::env;
+
+/* The shell's variables, set with 'NAME=value' and read with '$NAME'. The
+ * exported ones make up the environment of every command the shell runs. */
+pub struct VarStore {
+    vars: HashMap<String, String>,
+    exported: HashSet<String>,
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => { },
+        _ => return false,
+    }
+
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl VarStore {
+    pub fn new() -> VarStore {
+        VarStore {
+            vars: HashMap::new(),
+            exported: HashSet::new(),
+        }
+    }
+
+    /* Every variable in the shell's starting environment becomes an exported
+     * shell variable */
+    pub fn import_env(&mut self) {
+        for (name, value) in env::vars() {
+            self.exported.insert(name.clone());
+            self.vars.insert(name, value);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|s| s.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.vars.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+        self.exported.remove(name);
+    }
+
+    /* Marks a variable to be passed to commands. It doesn't have to be set
+     * yet. */
+    pub fn export(&mut self, name: &str) {
+        self.exported.insert(name.to_string());
+    }
+
+    /* The exported variables that are set, sorted by name */
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = self.exported.iter()
+                                                 .filter_map(|name| self.vars.get(name).map(|value| (name.clone(), value.clone())))
+                                                 .collect();
+        env.sort();
+        env
+    }
+}